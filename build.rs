@@ -1,3 +1,45 @@
+#[path = "src/libfuncs.rs"]
+mod libfuncs;
+
+use libfuncs::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn rust_type(typ: LibType) -> &'static str {
+  match typ {
+    LibType::Unit => "()",
+    LibType::Int => "i32",
+    LibType::IntPtr => "*mut i32",
+  }
+}
+
+// 为 runtime.rs 生成签名检查：每个声明过的函数都必须以完全相同的签名导出，
+// 否则运行时库编译失败，而不是等到链接编译出来的程序时才发现。
+fn gen_runtime_abi(out_dir: &Path) {
+  let mut checks = String::new();
+  for func in LIBFUNCS.iter() {
+    let params: Vec<&str> = func.params.iter().map(|p| rust_type(*p)).collect();
+    checks.push_str(&format!(
+      "const _: unsafe extern \"C\" fn({}) -> {} = {};\n",
+      params.join(", "),
+      rust_type(func.ret),
+      func.name
+    ));
+  }
+  fs::write(out_dir.join("runtime_abi.rs"), checks).unwrap();
+}
+
+// 仓库里的 sysylive.h 是给 C 程序用的，必须和 LIBFUNCS 一致，改了签名忘了重新生成时直接编译失败
+fn check_c_header() {
+  let committed = fs::read_to_string("sysylive.h").unwrap_or_default();
+  if committed != c_header() {
+    panic!("sysylive.h is out of date, regenerate it with `compiler --emit-runtime-header -o sysylive.h`");
+  }
+}
+
 fn main() {
   lalrpop::process_root().unwrap();
-}
\ No newline at end of file
+  gen_runtime_abi(Path::new(&env::var("OUT_DIR").unwrap()));
+  check_c_header();
+}
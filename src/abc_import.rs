@@ -0,0 +1,803 @@
+// 读取 ABC 记谱法写的曲子，生成和 Score x(syllablename = "...") 一样的小节。
+// K:、M:、L:、Q: 分别给出调号、拍号、默认音符长度和速度，可以写在头部，也可以写在曲子中间；
+// 反复记号和房子（|1、[2）交给 expand_repeats 展开。装饰音、和弦名、连线和渐强渐弱记号会被忽略。
+
+use crate::ast::*;
+use crate::diagnostic::*;
+use crate::gen_music::gcd;
+use crate::notation::{note_word, BarMark, ScoreBar};
+use std::collections::HashMap;
+
+pub struct AbcTune {
+    pub bars: Vec<ScoreBar>,
+    pub bpm: Option<i32>, // 第一个 Q: 换算成每分钟的四分音符数
+    pub meter: Option<(i32, i32)>, // 第一个 M:
+    pub key: Option<KeySignature>, // 第一个 K:
+}
+
+fn reduce(fz: i32, fm: i32) -> (i32, i32) {
+    let g = gcd(fz, fm);
+    (fz / g, fm / g)
+}
+
+fn abc_error(lo: usize, hi: usize, message: impl Into<String>) -> Diagnostic {
+    Diagnostic::error(Span::new(lo, hi), message)
+}
+
+/// 时值的分子分母（约分后）最大是多少，再大的后面累加小节拍数时会溢出
+const MAX_LENGTH: i32 = 1 << 16;
+
+/// 两个时值相乘再约分；超过 MAX_LENGTH 时返回 None
+fn scale(a: (i32, i32), b: (i32, i32)) -> Option<(i32, i32)> {
+    let (fz, fm) = reduce(a.0.checked_mul(b.0)?, a.1.checked_mul(b.1)?);
+    (fz <= MAX_LENGTH && fm <= MAX_LENGTH).then_some((fz, fm))
+}
+
+fn length_error(lo: usize, hi: usize) -> Diagnostic {
+    abc_error(lo, hi, "note length is out of range")
+}
+
+/// 字符串里的 \n、\"、\\ 换成对应的字符，同时记下每个字符在原文里的位置
+fn unescape(text: &str) -> Vec<(usize, char)> {
+    let mut ret = Vec::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            ret.push((i, c));
+            continue;
+        }
+        match chars.next() {
+            Some((_, 'n')) => ret.push((i, '\n')),
+            Some((_, 't')) => ret.push((i, ' ')),
+            Some((_, c)) => ret.push((i, c)),
+            None => ret.push((i, '\\')),
+        }
+    }
+    ret
+}
+
+/// 调号，例如 G、Bbm、Ador、D clef=bass、F ^c；返回每个音名（C 到 B）的升降，
+/// 以及要写进乐谱的调号（K:none 时没有）
+fn parse_key(value: &str) -> Option<([i32; 7], Option<KeySignature>)> {
+    let value = value.trim();
+    if value.is_empty() || value == "none" || value.eq_ignore_ascii_case("hp") {
+        return Some(([0; 7], None));
+    }
+    let mut chars = value.chars();
+    let step = "CDEFGAB".find(chars.next()?)? as i32;
+    let mut rest = chars.as_str();
+    let mut alter = 0;
+    if let Some(r) = rest.strip_prefix('#') {
+        (alter, rest) = (1, r);
+    } else if let Some(r) = rest.strip_prefix('b') {
+        (alter, rest) = (-1, r);
+    }
+    let mut words = rest.split_whitespace().peekable();
+    let mode = match words.peek() {
+        Some(w) if w.chars().all(|c| c.is_ascii_alphabetic()) => words.next().unwrap(),
+        _ => "",
+    };
+    let mode = mode.to_ascii_lowercase();
+    let mode = match mode.get(..3.min(mode.len())).unwrap() {
+        "" | "maj" | "ion" => "major",
+        "m" | "min" | "aeo" => "minor",
+        "mix" => "mixolydian",
+        "dor" => "dorian",
+        "phr" => "phrygian",
+        "lyd" => "lydian",
+        "loc" => "locrian",
+        _ => return None,
+    };
+    let signature = KeySignature::new(Spelling { step, alter }, mode);
+    let mut key = signature.alters();
+    // 后面可以再写临时加上的升降号，例如 ^f _b =c
+    for word in words {
+        let alter = match word.chars().next() {
+            Some('^') => 1,
+            Some('_') => -1,
+            Some('=') => 0,
+            _ => continue,
+        };
+        let letter = word[1..].chars().next()?.to_ascii_uppercase();
+        key["CDEFGAB".find(letter)?] = alter;
+    }
+    Some((key, Some(signature)))
+}
+
+/// 拍号：4/4、C（4/4）、C|（2/2）、none
+fn parse_meter(value: &str) -> Option<Option<(i32, i32)>> {
+    match value.trim() {
+        "none" | "" => Some(None),
+        "C" => Some(Some((4, 4))),
+        "C|" => Some(Some((2, 2))),
+        value => {
+            let (fz, fm) = value.split_once('/')?;
+            let (fz, fm) = (fz.trim().parse().ok()?, fm.trim().parse().ok()?);
+            let valid = 0 < fz && fz <= MAX_LENGTH && 0 < fm && fm <= MAX_LENGTH;
+            valid.then_some(Some((fz, fm)))
+        }
+    }
+}
+
+/// 分数，例如 1/8、3/8；只写分子时分母是 1
+fn parse_fraction(value: &str) -> Option<(i32, i32)> {
+    let (fz, fm) = match value.trim().split_once('/') {
+        Some((fz, fm)) => (fz.trim().parse().ok()?, fm.trim().parse().ok()?),
+        None => (value.trim().parse().ok()?, 1),
+    };
+    (fz > 0 && fm > 0).then(|| reduce(fz, fm))
+}
+
+/// 速度，例如 1/4=120、3/8=60、"Allegro" 1/4=120；只写一个数时以 L: 为一拍
+fn parse_tempo(value: &str, unit: (i32, i32)) -> Option<i32> {
+    // 去掉引号里的文字说明
+    let value: String = value.split('"').step_by(2).collect();
+    let (beat, bpm) = match value.split_once('=') {
+        Some((beats, bpm)) => {
+            let mut sum: (i32, i32) = (0, 1);
+            for beat in beats.split_whitespace() {
+                let (fz, fm) = parse_fraction(beat)?;
+                let fz = sum.0.checked_mul(fm)?.checked_add(fz.checked_mul(sum.1)?)?;
+                sum = reduce(fz, sum.1.checked_mul(fm)?);
+            }
+            (sum, bpm.trim().parse::<i32>().ok()?)
+        }
+        None => (unit, value.trim().parse::<i32>().ok()?),
+    };
+    if beat.0 <= 0 || bpm <= 0 {
+        return None;
+    }
+    Some(bpm.checked_mul(beat.0)?.checked_mul(4)? / beat.1)
+}
+
+struct Parser {
+    key: [i32; 7],
+    meter: Option<(i32, i32)>, // M: 原样的分子分母
+    unit: Option<(i32, i32)>,  // L:，以全音符为单位；没写时按拍号取 1/8 或 1/16
+    bpm: Option<i32>,
+    first_meter: Option<Option<(i32, i32)>>,
+    first_key: Option<Option<KeySignature>>,
+    bars: Vec<ScoreBar>,
+    notes: Vec<NoteElem>,
+    bar_span: Option<(usize, usize)>,
+    marks: Vec<(BarMark, Span)>,
+    repeat_start: bool,
+    altered: HashMap<(i32, i32), i32>, // 本小节里临时升降号：(音名, 八度) -> 升降
+    tie: bool,                          // 上一个音后面有 -
+    last_pitches: Vec<i32>,
+    last_note: Option<usize>,       // 上一个音在 notes 里的下标，附点节奏 > < 要改它的时值
+    broken: (i32, i32),             // 附点节奏留给下一个音的倍数
+    tuplet: Option<(i32, i32, i32)>, // (p, q, 还剩几个音)：p 个音占 q 个音的时间
+    articulation: i32,
+    dynamics: Vec<NoteElem>,
+}
+
+impl Parser {
+    fn unit(&self) -> (i32, i32) {
+        self.unit.unwrap_or(match self.meter {
+            Some((fz, fm)) if (fz as i64) * 4 < (fm as i64) * 3 => (1, 16),
+            _ => (1, 8),
+        })
+    }
+
+    fn field(&mut self, name: char, value: &str, lo: usize, hi: usize) -> Result<(), Diagnostic> {
+        let invalid = || abc_error(lo, hi, format!("invalid ABC field `{}:{}`", name, value.trim()));
+        match name {
+            'K' => {
+                let (key, signature) = parse_key(value).ok_or_else(invalid)?;
+                self.key = key;
+                self.first_key.get_or_insert(signature);
+            }
+            'M' => {
+                self.meter = parse_meter(value).ok_or_else(invalid)?;
+                self.first_meter.get_or_insert(self.meter);
+            }
+            'L' => self.unit = Some(parse_fraction(value).ok_or_else(invalid)?),
+            'Q' => {
+                let bpm = parse_tempo(value, self.unit()).ok_or_else(invalid)?;
+                self.bpm.get_or_insert(bpm);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 小节线：结束当前小节，repeat_end 是 :|，repeat_start 是 |:
+    fn bar_line(&mut self, repeat_end: bool, repeat_start: bool) {
+        if let Some((lo, hi)) = self.bar_span.take() {
+            let bar = Bar {
+                notes: std::mem::take(&mut self.notes),
+                span: Span::new(lo, hi),
+            };
+            let mut bar = ScoreBar::new(bar, std::mem::take(&mut self.marks));
+            bar.repeat_start = std::mem::take(&mut self.repeat_start);
+            self.bars.push(bar);
+        }
+        if repeat_end {
+            if let Some(bar) = self.bars.last_mut() {
+                bar.repeat_end = true;
+            }
+        }
+        self.repeat_start |= repeat_start;
+        self.altered.clear();
+        self.last_note = None;
+    }
+
+    /// 加一个音（pitches 为空时是休止符），时值是 len 个 L:
+    fn push(
+        &mut self,
+        pitches: Vec<Note>,
+        len: (i32, i32),
+        lo: usize,
+        hi: usize,
+    ) -> Result<(), Diagnostic> {
+        let unit = self.unit();
+        let mut len = scale(len, unit)
+            .and_then(|len| scale(len, (4, 1)))
+            .and_then(|len| scale(len, self.broken));
+        self.broken = (1, 1);
+        if let Some((p, q, left)) = self.tuplet {
+            len = len.and_then(|len| scale(len, (q, p)));
+            self.tuplet = (left > 1).then_some((p, q, left - 1));
+        }
+        let (fz, fm) = len.ok_or_else(|| length_error(lo, hi))?;
+        let keys: Vec<i32> = pitches.iter().filter_map(|n| n.semitone()).collect();
+        let elem = if std::mem::take(&mut self.tie) && !keys.is_empty() && keys == self.last_pitches {
+            NoteElem::Tie
+        } else {
+            let elem = match pitches.len() {
+                0 => NoteElem::Note(Note::Rest),
+                1 => NoteElem::Note(pitches.into_iter().next().unwrap()),
+                _ => NoteElem::Chord(pitches),
+            };
+            match std::mem::take(&mut self.articulation) {
+                0 => elem,
+                flags => NoteElem::Articulated(Box::new(elem), flags),
+            }
+        };
+        self.last_pitches = keys;
+        self.notes.append(&mut self.dynamics);
+        self.notes.push(NoteElem::Scaled(Box::new(elem), fz, fm));
+        self.last_note = Some(self.notes.len() - 1);
+        let (bar_lo, _) = self.bar_span.unwrap_or((lo, hi));
+        self.bar_span = Some((bar_lo, hi));
+        Ok(())
+    }
+
+    fn decoration(&mut self, name: &str, lo: usize, hi: usize) {
+        match name {
+            "staccato" => self.articulation |= STACCATO,
+            "tenuto" => self.articulation |= TENUTO,
+            "accent" | "emphasis" | ">" => self.articulation |= ACCENT,
+            "fermata" => self.articulation |= FERMATA,
+            _ => {
+                if let Ok(dynamic @ NoteElem::Dynamic(Dynamic::Level(_), _)) =
+                    note_word(name, Span::new(lo, hi))
+                {
+                    self.dynamics.push(dynamic);
+                }
+            }
+        }
+    }
+}
+
+/// 一行音乐里的位置
+struct Cursor<'a> {
+    chars: &'a [(usize, char)],
+    i: usize,
+    end: usize, // 行尾在原文里的位置
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).map(|c| c.1)
+    }
+
+    fn peek_at(&self, k: usize) -> Option<char> {
+        self.chars.get(self.i + k).map(|c| c.1)
+    }
+
+    fn pos(&self) -> usize {
+        self.chars.get(self.i).map_or(self.end, |c| c.0)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let ret = self.peek() == Some(c);
+        if ret {
+            self.i += 1;
+        }
+        ret
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        let mut ret: Option<i32> = None;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            ret = Some(ret.unwrap_or(0).saturating_mul(10).saturating_add(d as i32));
+            self.i += 1;
+        }
+        ret
+    }
+
+    /// 音符后面的长度：2、3/2、/、//、/4；太长或太短时返回 None
+    fn length(&mut self) -> Option<(i32, i32)> {
+        let mut len = scale((self.number().unwrap_or(1).max(1), 1), (1, 1));
+        while self.eat('/') {
+            let fm = self.number().map_or(2, |n| n.max(1));
+            len = len.and_then(|len| scale(len, (1, fm)));
+        }
+        len
+    }
+
+    /// 到 close 为止的文字（不含 close），找不到 close 时返回 None
+    fn until(&mut self, close: char) -> Option<String> {
+        let mut ret = String::new();
+        while let Some(c) = self.peek() {
+            self.i += 1;
+            if c == close {
+                return Some(ret);
+            }
+            ret.push(c);
+        }
+        None
+    }
+}
+
+impl Parser {
+    /// 一个音：升降号、音名、八度记号和长度。返回 None 表示休止符
+    fn note(&mut self, cur: &mut Cursor) -> Result<Option<Note>, Diagnostic> {
+        let lo = cur.pos();
+        let mut explicit = None;
+        if cur.eat('^') {
+            explicit = Some(if cur.eat('^') { 2 } else { 1 });
+        } else if cur.eat('_') {
+            explicit = Some(if cur.eat('_') { -2 } else { -1 });
+        } else if cur.eat('=') {
+            explicit = Some(0);
+        }
+        let letter = cur.peek();
+        let (step, mut octave) = match letter {
+            Some(c @ 'A'..='G') => ("CDEFGAB".find(c).unwrap() as i32, 4),
+            Some(c @ 'a'..='g') => ("cdefgab".find(c).unwrap() as i32, 5),
+            Some('z' | 'x') if explicit.is_none() => {
+                cur.i += 1;
+                return Ok(None);
+            }
+            _ => return Err(abc_error(lo, cur.pos() + 1, "expected a note name after the accidental")),
+        };
+        cur.i += 1;
+        loop {
+            if cur.eat('\'') {
+                octave += 1;
+            } else if cur.eat(',') {
+                octave -= 1;
+            } else {
+                break;
+            }
+        }
+        // 临时升降号在本小节里对同一个八度的同名音一直有效
+        let alter = match explicit {
+            Some(alter) => {
+                self.altered.insert((step, octave), alter);
+                alter
+            }
+            None => *self.altered.get(&(step, octave)).unwrap_or(&self.key[step as usize]),
+        };
+        let spelling = Spelling { step, alter };
+        Ok(Some(Note::Pitch(spelling.semitone() + (octave - 4) * 12, spelling)))
+    }
+
+    fn line(&mut self, cur: &mut Cursor) -> Result<(), Diagnostic> {
+        while let Some(c) = cur.peek() {
+            let lo = cur.pos();
+            match c {
+                '%' => break,
+                ' ' | '\t' | '\r' | '`' | '\\' | ')' | '~' | 'T' | 'u' | 'v' | 'M' | 'P' | 'S'
+                | 'O' => cur.i += 1,
+                'H' => {
+                    cur.i += 1;
+                    self.articulation |= FERMATA;
+                }
+                'L' => {
+                    cur.i += 1;
+                    self.articulation |= ACCENT;
+                }
+                '.' => {
+                    cur.i += 1;
+                    if cur.peek() != Some('|') {
+                        self.articulation |= STACCATO;
+                    }
+                }
+                '-' => {
+                    cur.i += 1;
+                    self.tie = true;
+                }
+                '"' | '!' | '+' | '{' => {
+                    cur.i += 1;
+                    let close = if c == '{' { '}' } else { c };
+                    let text = cur
+                        .until(close)
+                        .ok_or_else(|| abc_error(lo, cur.end, format!("unclosed `{}`", c)))?;
+                    if c == '!' || c == '+' {
+                        self.decoration(&text, lo, cur.pos());
+                    }
+                }
+                '(' => {
+                    cur.i += 1;
+                    // (p:q:r 连音；否则是连线的开始，忽略
+                    if let Some(p) = cur.number() {
+                        let compound = matches!(self.meter, Some((fz, _)) if fz % 3 == 0 && fz > 3);
+                        let mut q = match p {
+                            2 | 4 | 8 => 3,
+                            3 | 6 => 2,
+                            _ if compound => 3,
+                            _ => 2,
+                        };
+                        let mut r = p;
+                        if cur.eat(':') {
+                            q = cur.number().unwrap_or(q);
+                            if cur.eat(':') {
+                                r = cur.number().unwrap_or(r);
+                            }
+                        }
+                        if p <= 0 || q <= 0 {
+                            return Err(abc_error(lo, cur.pos(), "invalid tuplet"));
+                        }
+                        self.tuplet = (r > 0).then_some((p, q, r));
+                    }
+                }
+                '>' | '<' => {
+                    let mut n = 0;
+                    while cur.eat(c) {
+                        n += 1;
+                    }
+                    let Some(NoteElem::Scaled(_, fz, fm)) = self.last_note.map(|k| &mut self.notes[k])
+                    else {
+                        return Err(abc_error(lo, cur.pos(), format!("`{}` must follow a note", c)));
+                    };
+                    if n > 8 {
+                        return Err(length_error(lo, cur.pos()));
+                    }
+                    // A>B 是附点：前一个音长一半，后一个音短一半；>> 是双附点
+                    let (longer, shorter) = (((2 << n) - 1, 1 << n), (1, 1 << n));
+                    let (this, next) = if c == '>' { (longer, shorter) } else { (shorter, longer) };
+                    (*fz, *fm) =
+                        scale((*fz, *fm), this).ok_or_else(|| length_error(lo, cur.pos()))?;
+                    self.broken = next;
+                }
+                '[' if cur.peek_at(1).is_some_and(|c| c.is_ascii_alphabetic())
+                    && cur.peek_at(2) == Some(':') =>
+                {
+                    let name = cur.peek_at(1).unwrap();
+                    cur.i += 3;
+                    let value = cur
+                        .until(']')
+                        .ok_or_else(|| abc_error(lo, cur.end, "unclosed inline field"))?;
+                    self.field(name, &value, lo, cur.pos())?;
+                }
+                '[' if cur.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => {
+                    cur.i += 1;
+                    self.volta(cur, lo);
+                }
+                '|' | ':' | '[' if c != '[' || cur.peek_at(1) == Some('|') => {
+                    let mut text = String::new();
+                    while let Some(c @ ('|' | ':' | ']' | '[')) = cur.peek() {
+                        // |[CEG] 里的 [ 是和弦的开始
+                        if c == '[' && !text.is_empty() && cur.peek_at(1) != Some('|') {
+                            break;
+                        }
+                        text.push(c);
+                        cur.i += 1;
+                    }
+                    let repeat_end = text.starts_with(':');
+                    let repeat_start = text.ends_with(':') && (text.len() > 1 || !repeat_end);
+                    self.bar_line(repeat_end, repeat_start);
+                    if cur.peek() == Some('[') && cur.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+                        cur.i += 1;
+                    }
+                    if cur.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        self.volta(cur, lo);
+                    }
+                }
+                '[' => {
+                    cur.i += 1;
+                    let mut pitches = Vec::new();
+                    let mut inner = None;
+                    while !cur.eat(']') {
+                        match cur.peek() {
+                            None => return Err(abc_error(lo, cur.end, "unclosed chord")),
+                            Some(' ' | '-') => cur.i += 1,
+                            Some('"' | '!' | '+') => {
+                                let close = cur.peek().unwrap();
+                                cur.i += 1;
+                                cur.until(close);
+                            }
+                            Some(_) => {
+                                let note = self.note(cur)?;
+                                let len =
+                                    cur.length().ok_or_else(|| length_error(lo, cur.pos()))?;
+                                inner.get_or_insert(len);
+                                pitches.extend(note);
+                            }
+                        }
+                    }
+                    let len = cur
+                        .length()
+                        .and_then(|len| scale(inner.unwrap_or((1, 1)), len))
+                        .ok_or_else(|| length_error(lo, cur.pos()))?;
+                    self.push(pitches, len, lo, cur.pos())?;
+                }
+                'Z' => {
+                    cur.i += 1;
+                    let count = cur.number().unwrap_or(1);
+                    let Some((fz, fm)) = self.meter else {
+                        return Err(abc_error(lo, cur.pos(), "`Z` needs a meter (M: field)"));
+                    };
+                    // 整小节休止，Z4 是连续 4 个小节
+                    let unit = self.unit();
+                    let len = scale((fz, fm), (unit.1, unit.0))
+                        .filter(|_| count <= MAX_LENGTH)
+                        .ok_or_else(|| length_error(lo, cur.pos()))?;
+                    for k in 0..count.max(1) {
+                        if k > 0 {
+                            self.bar_line(false, false);
+                        }
+                        self.push(Vec::new(), len, lo, cur.pos())?;
+                    }
+                }
+                'y' => {
+                    cur.i += 1;
+                    cur.length();
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' | 'z' | 'x' => {
+                    let note = self.note(cur)?;
+                    let len = cur.length().ok_or_else(|| length_error(lo, cur.pos()))?;
+                    self.push(note.into_iter().collect(), len, lo, cur.pos())?;
+                }
+                _ => {
+                    return Err(abc_error(
+                        lo,
+                        lo + c.len_utf8(),
+                        format!("unexpected `{}` in ABC tune", c),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 小节线后面的房子编号，例如 |1、:|2、[1,3；只取第一个编号
+    fn volta(&mut self, cur: &mut Cursor, lo: usize) {
+        let num = cur.number().unwrap_or(1);
+        while cur.peek().is_some_and(|c| c.is_ascii_digit() || c == ',' || c == '-') {
+            cur.i += 1;
+        }
+        self.marks
+            .push((BarMark::Volta(num), Span::new(lo, cur.pos())));
+    }
+}
+
+/// 解析 ABC 曲子；text 是声明里写的原文，转义的 \n 当作换行。
+/// 返回的位置都相对于 text 的开头，只读第一首曲子（第二个 X: 之前）
+pub fn import_abc(text: &str) -> Result<AbcTune, Diagnostic> {
+    let chars = unescape(text);
+    let mut parser = Parser {
+        key: [0; 7],
+        meter: None,
+        unit: None,
+        bpm: None,
+        first_meter: None,
+        first_key: None,
+        bars: Vec::new(),
+        notes: Vec::new(),
+        bar_span: None,
+        marks: Vec::new(),
+        repeat_start: false,
+        altered: HashMap::new(),
+        tie: false,
+        last_pitches: Vec::new(),
+        last_note: None,
+        broken: (1, 1),
+        tuplet: None,
+        articulation: 0,
+        dynamics: Vec::new(),
+    };
+    let mut tunes = 0;
+    for line in chars.split(|c| c.1 == '\n') {
+        let end = line.last().map_or(0, |c| c.0 + 1);
+        let trimmed: Vec<char> = line.iter().map(|c| c.1).skip_while(|c| *c == ' ').take(2).collect();
+        if let [name, ':'] = trimmed[..] {
+            if name.is_ascii_alphabetic() {
+                let start = line.iter().position(|c| c.1 == ':').unwrap() + 1;
+                let value: String = line[start..].iter().map(|c| c.1).collect();
+                let value = value.split('%').next().unwrap();
+                if name == 'X' {
+                    tunes += 1;
+                    if tunes > 1 {
+                        break;
+                    }
+                }
+                parser.field(name, value, line[0].0, end)?;
+                continue;
+            }
+        }
+        let mut cur = Cursor { chars: line, i: 0, end };
+        parser.line(&mut cur)?;
+    }
+    parser.bar_line(false, false);
+    if parser.bars.is_empty() {
+        return Err(abc_error(0, text.len(), "ABC tune has no notes"));
+    }
+    Ok(AbcTune {
+        bars: parser.bars,
+        bpm: parser.bpm,
+        meter: parser.first_meter.flatten(),
+        key: parser.first_key.flatten(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_music::{for_each_note, NoteStyle};
+
+    /// 每个小节里的音写成 "音高:时值"，休止符是 r，延音是 -
+    fn notes(tune: &AbcTune) -> Vec<Vec<String>> {
+        let mut ret = Vec::new();
+        for bar in tune.bars.iter() {
+            let mut words = Vec::new();
+            for elem in bar.bar.notes.iter() {
+                for_each_note(elem, 1, 1, NoteStyle::default(), &mut |elem, fz, fm, _| {
+                    let pitch = match elem {
+                        NoteElem::Note(Note::Rest) => "r".to_string(),
+                        NoteElem::Note(note) => note.semitone().unwrap().to_string(),
+                        NoteElem::Chord(notes) => {
+                            let keys: Vec<String> = notes
+                                .iter()
+                                .map(|n| n.semitone().unwrap().to_string())
+                                .collect();
+                            format!("[{}]", keys.join(" "))
+                        }
+                        _ => "-".to_string(),
+                    };
+                    words.push(format!("{}:{}/{}", pitch, fz, fm));
+                });
+            }
+            ret.push(words);
+        }
+        ret
+    }
+
+    #[test]
+    fn key_signatures() {
+        let tune = import_abc("X:1\\nL:1/4\\nK:G\\nF f =F F | F").unwrap();
+        assert_eq!(
+            tune.key,
+            Some(KeySignature {
+                fifths: 1,
+                mode: "major"
+            })
+        );
+        // 临时记号在本小节里对同一八度的同名音有效，下一小节回到调号
+        assert_eq!(
+            notes(&tune),
+            [vec!["6:1/1", "18:1/1", "5:1/1", "5:1/1"], vec!["6:1/1"]]
+        );
+        let key = |k: &str| import_abc(&format!("K:{}\\nC", k)).unwrap().key;
+        assert_eq!(
+            key("Ador"),
+            Some(KeySignature {
+                fifths: 1,
+                mode: "dorian"
+            })
+        );
+        assert_eq!(
+            key("Bbm"),
+            Some(KeySignature {
+                fifths: -5,
+                mode: "minor"
+            })
+        );
+        assert_eq!(
+            key("D Mixolydian"),
+            Some(KeySignature {
+                fifths: 1,
+                mode: "mixolydian"
+            })
+        );
+        assert_eq!(key("none"), None);
+        // 调号后面加上的升降号只影响演奏，不改写出来的调号
+        let tune = import_abc("L:1/4\\nK:D ^g\\nG").unwrap();
+        assert_eq!(
+            tune.key,
+            Some(KeySignature {
+                fifths: 2,
+                mode: "major"
+            })
+        );
+        assert_eq!(notes(&tune), [vec!["8:1/1"]]);
+    }
+
+    #[test]
+    fn invalid_key() {
+        let err = import_abc("X:1\\nK:Cfoo\\nC").err().unwrap();
+        assert_eq!(err.message, "invalid ABC field `K:Cfoo`");
+        assert_eq!(err.span, Span::new(5, 11));
+        let err = import_abc("K:H\\nC").err().unwrap();
+        assert_eq!(err.message, "invalid ABC field `K:H`");
+    }
+
+    #[test]
+    fn meters() {
+        // 不写 L: 时按拍号取 1/8 或 1/16
+        let tune = import_abc("M:6/8\\nK:C\\nABc d2e | Z").unwrap();
+        assert_eq!(tune.meter, Some((6, 8)));
+        assert_eq!(
+            notes(&tune)[0],
+            ["9:1/2", "11:1/2", "12:1/2", "14:1/1", "16:1/2"]
+        );
+        assert_eq!(notes(&tune)[1], ["r:3/1"]);
+        let tune = import_abc("M:2/4\\nK:C\\nA4 B4").unwrap();
+        assert_eq!(notes(&tune)[0], ["9:1/1", "11:1/1"]);
+        assert_eq!(import_abc("M:C|\\nK:C\\nC").unwrap().meter, Some((2, 2)));
+        // 第一个 M: 是写进乐谱的拍号，曲子中间的拍号只影响 Z
+        let tune = import_abc("M:4/4\\nK:C\\nC8 | [M:3/4] Z2 |").unwrap();
+        assert_eq!(tune.meter, Some((4, 4)));
+        assert_eq!(notes(&tune)[1..], [vec!["r:3/1"], vec!["r:3/1"]]);
+        let err = import_abc("M:7\\nK:C\\nC").err().unwrap();
+        assert_eq!(err.message, "invalid ABC field `M:7`");
+        let err = import_abc("K:C\\nC | Z |").err().unwrap();
+        assert_eq!(err.message, "`Z` needs a meter (M: field)");
+        // 放不进 i32 的长度、速度报错而不是溢出
+        let long = |text: &str| import_abc(text).err().unwrap().message;
+        assert_eq!(
+            long("X:1\\nL:1/4\\nK:C\\nC99999999999 |"),
+            "note length is out of range"
+        );
+        let slashes = format!("K:C\\nC{}", "/".repeat(38));
+        assert_eq!(long(&slashes), "note length is out of range");
+        assert_eq!(
+            long("K:C\\nC>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>D"),
+            "note length is out of range"
+        );
+        assert_eq!(
+            long("M:2000000000/1\\nK:C\\nC"),
+            "invalid ABC field `M:2000000000/1`"
+        );
+        assert_eq!(
+            long("M:60000/1\\nL:1/60000\\nK:C\\nZ"),
+            "note length is out of range"
+        );
+        assert_eq!(
+            long("M:4/4\\nK:C\\nZ99999999999"),
+            "note length is out of range"
+        );
+        assert_eq!(
+            long("Q:1/4=2000000000\\nK:C\\nC"),
+            "invalid ABC field `Q:1/4=2000000000`"
+        );
+        assert_eq!(
+            long("Q:1/2000000000 1/3=60\\nK:C\\nC"),
+            "invalid ABC field `Q:1/2000000000 1/3=60`"
+        );
+    }
+
+    #[test]
+    fn tuplets() {
+        let tune = import_abc("L:1/8\\nK:C\\n(3CDE F2 | (2CD (3:2:4C2DEF G | (5CDEFG").unwrap();
+        let bars = notes(&tune);
+        assert_eq!(bars[0], ["0:1/3", "2:1/3", "4:1/3", "5:1/1"]);
+        // (2 是两个音占三个音的时间，(3:2:4 一直作用到第四个音
+        assert_eq!(
+            bars[1],
+            ["0:3/4", "2:3/4", "0:2/3", "2:1/3", "4:1/3", "5:1/3", "7:1/2"]
+        );
+        assert_eq!(bars[2].len(), 5);
+        assert!(bars[2].iter().all(|n| n.ends_with(":1/5")));
+        let err = import_abc("K:C\\n(3:0 CDE").err().unwrap();
+        assert_eq!(err.message, "invalid tuplet");
+        let err = import_abc("K:C\\n(99999999999:2 CDE").err().unwrap();
+        assert_eq!(err.message, "note length is out of range");
+        let err = import_abc("K:C\\n(3 C2 [CE]99999999999/ E").err().unwrap();
+        assert_eq!(err.message, "note length is out of range");
+    }
+}
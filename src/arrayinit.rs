@@ -1,5 +1,6 @@
 use crate::ast::*;
 use crate::constint::*;
+use crate::diagnostic::*;
 use crate::ident::*;
 use koopa::ir::builder::LocalInstBuilder;
 use koopa::ir::builder::ValueBuilder;
@@ -14,6 +15,38 @@ pub fn gen_arraytype(lens: Vec<i32>) -> Type {
     return curtype;
 }
 
+/// 计算数组各维的长度，长度必须是正的常数；出错时报错并当作 1 处理
+pub fn gen_arraylens(
+    data: &mut FunctionData,
+    entry: &mut BasicBlock,
+    var: &mut HashMap<String, IdentValue>,
+    id: &str,
+    exps: &[Box<Exp>],
+) -> Vec<i32> {
+    let mut lens: Vec<i32> = Vec::new();
+    for exp in exps.iter() {
+        let val = exp.gen_ir(data, entry, var);
+        match get_const_int(data, val) {
+            Some(res) if res > 0 => lens.push(res),
+            Some(res) => {
+                report(Diagnostic::error(
+                    exp.span,
+                    format!("array size of `{}` must be positive, found {}", id, res),
+                ));
+                lens.push(1);
+            }
+            None => {
+                report(Diagnostic::error(
+                    exp.span,
+                    format!("array size of `{}` is not a constant", id),
+                ));
+                lens.push(1);
+            }
+        }
+    }
+    lens
+}
+
 pub fn find_firstok(curpos: i32, lens: Vec<i32>) -> (i32, i32) {
     let mut i: i32 = lens.len() as i32 - 1;
     let mut firstok: i32 = i as i32;
@@ -68,23 +101,39 @@ pub fn gen_globalarrayinit(
 ) -> Vec<i32> {
     let mut curpos = 0;
     let mut curvec: Vec<i32> = Vec::new();
+    let all = lens.iter().fold(1, |acc, x| acc * x);
     match initer {
-        ArrayInit::Single(_) => panic!("using an exp to init a global array"),
-        ArrayInit::Multiple(inits) => {
+        ArrayInit::Single(exp) => report(Diagnostic::error(
+            exp.span,
+            "global array must be initialized with `{ ... }`",
+        )),
+        ArrayInit::Multiple(inits, span) => {
             for cur in inits.iter() {
+                if curpos >= all {
+                    report(Diagnostic::error(
+                        span,
+                        "too many elements in array initializer",
+                    ));
+                    break;
+                }
                 match cur.as_ref() {
                     ArrayInit::Single(exp) => {
                         let val = exp.gen_ir(data, entry, var);
                         if let Some(rv) = get_const_int(data, val) {
                             curvec.push(rv);
                         } else {
-                            panic!("using a not const exp to init global array");
+                            report(Diagnostic::error(
+                                exp.span,
+                                "global array initializer is not a constant",
+                            ));
+                            curvec.push(0);
                         }
                         curpos += 1;
                     }
-                    ArrayInit::Multiple(_) => {
-                        if curpos % lens.last().unwrap() != 0 {
-                            panic!("bad array initializer");
+                    ArrayInit::Multiple(_, subspan) => {
+                        if lens.is_empty() || curpos % lens.last().unwrap() != 0 {
+                            report(Diagnostic::error(*subspan, "bad array initializer"));
+                            break;
                         }
                         let respr = find_firstok(curpos, lens.clone());
                         let firstok: i32 = std::cmp::max(respr.0, 1);
@@ -104,13 +153,13 @@ pub fn gen_globalarrayinit(
                     }
                 }
             }
-            let all = lens.iter().fold(1, |acc, x| acc * x);
-            while curpos < all {
-                curvec.push(0);
-                curpos += 1;
-            }
         }
     }
+    curvec.truncate(all as usize);
+    while curpos < all {
+        curvec.push(0);
+        curpos += 1;
+    }
     return curvec;
 }
 
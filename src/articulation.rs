@@ -0,0 +1,7 @@
+// 演奏法后缀对应的标志位，编译器（ast.rs）和运行时库（render.rs）共用这一份，
+// 编译出来的程序把几个标志位的组合作为 Note 的 articulation 传给运行时。
+
+pub const STACCATO: i32 = 1; // ' 断奏：只响一半，剩下的时间静音
+pub const TENUTO: i32 = 2; // ~ 保持：稍微延续到下一个音里
+pub const ACCENT: i32 = 4; // > 重音：加强起音
+pub const FERMATA: i32 = 8; // ^ 延长：多停留一半的时值
@@ -1,7 +1,11 @@
+pub use crate::articulation::*; // 演奏法后缀的标志位，和运行时库共用
+use crate::diagnostic::Span;
+
 #[derive(Debug, Clone)]
 pub struct CompUnit {
     pub func_defs: Vec<FuncDef>,
     pub global_var_defs: Vec<Decl>,
+    pub strings: Vec<String>, // 所有字符串字面量，各自生成一个全局数组
 }
 
 #[derive(Debug, Clone)]
@@ -14,8 +18,19 @@ pub enum GlobalDef {
 pub enum FuncParam {
     Var(String),
     Array(String, Vec<Box<Exp>>),
+    Music(MusicType, String),
+}
+// 除了 int 以外，音乐对象也可以作为参数和返回值。
+// Bar 和 Score 传参时复制一份（和 Bar x(y) 一样），Note 不可变，Track 绑定的是文件，Instrument 只能添加采样，都直接传句柄。
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MusicType {
+    Note,
+    Bar,
+    Score,
+    Track,
+    Instrument,
 }
-// 目前只有 int 一个类型。
 
 #[derive(Debug, Clone)]
 pub struct FuncDef {
@@ -23,12 +38,14 @@ pub struct FuncDef {
     pub params: Vec<FuncParam>,
     pub id: String,
     pub block: Block,
+    pub span: Span, // 函数名的位置，用于报错
 }
 
 #[derive(Debug, Clone)]
 pub enum FuncType {
     Int,
     Void,
+    Music(MusicType),
 }
 
 #[derive(Debug, Clone)]
@@ -46,15 +63,17 @@ pub enum BlockItem {
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Assign(LVal, Box<Exp>),
-    Return(Box<Option<Exp>>),
+    Return(Box<Option<Exp>>, Span),
     Do(Box<Option<Exp>>),
     Block(Box<Block>),
     If(Box<Exp>, Box<Stmt>),
     IfElse(Box<Exp>, Box<Stmt>, Box<Stmt>),
     While(Box<Exp>, Box<Stmt>),
-    Break,
-    Continue,
+    Break(Span),
+    Continue(Span),
     Sing(Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>),
+    SingInstrument(Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>), // 音源是 Instrument，由 typecheck 改写
+    SingPreset(Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>),
     PushBar(Box<Exp>, Box<Exp>),
     PushNote(Box<Exp>, Box<Exp>),
     SetBarBpm(Box<Exp>, Box<Exp>),
@@ -64,9 +83,15 @@ pub enum Stmt {
     IncScorePitch(Box<Exp>, Box<Exp>),
     IncBarPitch(Box<Exp>, Box<Exp>),
     SetScoreDuration(Box<Exp>, Box<Exp>),
+    SetEnvelope(Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>),
+    AddSample(Box<Exp>, Box<Exp>, Box<Exp>),
+    ExportMidi(Box<Exp>, Box<Exp>),
     SetBarDuration(Box<Exp>, Box<Exp>),
     AppendTrack(Box<Exp>, Box<Exp>),
     StackTrack(Box<Exp>, Box<Exp>),
+    StackTrackAt(Box<Exp>, Box<Exp>, Box<Exp>),
+    Pan(Box<Exp>, Box<Exp>),
+    GainDb(Box<Exp>, Box<Exp>),
     SetVol(Box<Exp>, Box<Exp>, Box<Exp>),
     AppendSilence(Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>),
 }
@@ -74,12 +99,13 @@ pub enum Stmt {
 #[derive(Debug, Clone)]
 pub struct Exp {
     pub lorexp: Box<LOrExp>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub enum LVal {
-    Ident(String),
-    Array(String, Vec<Box<Exp>>),
+    Ident(String, Span),
+    Array(String, Vec<Box<Exp>>, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -87,6 +113,7 @@ pub enum PrimaryExp {
     Exp(Box<Exp>),
     LVal(LVal),
     Number(i32),
+    Str(String, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -97,7 +124,7 @@ pub struct Decl {
 #[derive(Debug, Clone)]
 pub enum ArrayInit {
     Single(Box<Exp>),
-    Multiple(Vec<Box<ArrayInit>>),
+    Multiple(Vec<Box<ArrayInit>>, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -117,12 +144,14 @@ pub enum VarDef {
     Track(String),
     TrackLoad(String, Box<Exp>),
     TrackCopy(String, Box<Exp>, Box<Exp>),
+    Instrument(String),
+    MusicBind(MusicType, String, Box<Exp>), // Bar b = f(); 绑定到已有的对象，不复制
 }
 
 #[derive(Debug, Clone)]
 pub enum UnaryExp {
     PrimaryExp(Box<PrimaryExp>),
-    FuncCall(String, Vec<Box<Exp>>),
+    FuncCall(String, Vec<Box<Exp>>, Span),
     Pos(Box<UnaryExp>),
     Neg(Box<UnaryExp>),
     Not(Box<UnaryExp>),
@@ -171,17 +200,69 @@ pub enum LOrExp {
     Or(Box<LOrExp>, Box<LAndExp>),
 }
 
+/// 音符的写法：音名（C D E F G A B 记作 0 到 6）和升降号，导出乐谱时用来还原 b、# 的写法。
+/// 唱名在定调之前按 C 调记，1 就是 C，b3 就是 Eb
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spelling {
+    pub step: i32,
+    pub alter: i32,
+}
+
+const NATURAL: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+impl Spelling {
+    pub const C: Spelling = Spelling { step: 0, alter: 0 };
+
+    /// 没有写法可依时（例如从 MIDI 导入）的常用写法
+    pub fn of(half: i32) -> Spelling {
+        let (step, alter) = [(0, 0), (0, 1), (1, 0), (2, -1), (2, 0), (3, 0), (3, 1), (4, 0), (5, -1), (5, 0), (6, -1), (6, 0)]
+            [half.rem_euclid(12) as usize];
+        Spelling { step, alter }
+    }
+
+    /// 在 C4 所在的八度里是 C4 + 几个半音
+    pub fn semitone(self) -> i32 {
+        NATURAL[self.step as usize] + self.alter
+    }
+
+    /// 音高为 C4 + half 个半音时所在的八度，C4 在第 4 个八度
+    pub fn octave(self, half: i32) -> i32 {
+        4 + (half - self.semitone()).div_euclid(12)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Note {
-    Semitone(i32),
+    Semitone(i32, Spelling), // 唱名，do+几个半音，会随调号移动
+    Pitch(i32, Spelling),    // 字母音名，C4+几个半音，不随调号移动
     Rest,
 }
 
 impl Note {
     pub fn up(self, num: i32) -> Note {
         match self {
-            Note::Semitone(n) => Note::Semitone(n + num * 12),
-            Note::Rest => Note::Rest,
+            Note::Semitone(n, spelling) => Note::Semitone(n + num * 12, spelling),
+            other => other,
+        }
+    }
+
+    pub fn semitone(&self) -> Option<i32> {
+        match self {
+            Note::Semitone(n, _) | Note::Pitch(n, _) => Some(*n),
+            Note::Rest => None,
+        }
+    }
+
+    /// 定调之后唱名的写法也换成实际的音名，例如 Eb 调的 3 写作 G
+    fn transpose_degrees(&mut self, key: Spelling) {
+        if let Note::Semitone(n, spelling) = self {
+            *n += key.semitone();
+            let step = (spelling.step + key.step) % 7;
+            let alter = (*n - NATURAL[step as usize]).rem_euclid(12);
+            *spelling = Spelling {
+                step,
+                alter: if alter > 6 { alter - 12 } else { alter },
+            };
         }
     }
 }
@@ -191,14 +272,114 @@ pub enum NoteElem {
     Tie,
     Note(Note),
     Notes(Vec<Box<NoteElem>>),
+    Chord(Vec<Note>), // 同时发声的几个音，不含休止符
+    Scaled(Box<NoteElem>, i32, i32), // 时值乘上 fz/fm，来自附点和 /n、*n 后缀
+    Dynamic(Dynamic, Span),          // 力度记号，不占时值，编译期换算成后面各音的力度
+    Velocity(Box<NoteElem>, i32),    // 力度记号换算出来的力度，只包着音符或和弦
+    Articulated(Box<NoteElem>, i32), // 演奏法后缀，STACCATO 等的组合
+}
+
+/// 默认力度，也就是 mf
+pub const DEFAULT_VELOCITY: i32 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dynamic {
+    Level(i32), // pp、p、mf 等，值是 MIDI 的力度
+    Cresc,      // 渐强，到下一个力度记号为止
+    Dim,        // 渐弱，到下一个力度记号为止
 }
 
 #[derive(Debug, Clone)]
 pub struct Bar {
     pub notes: Vec<NoteElem>,
+    pub span: Span, // 在谱字符串里的位置，用于报错
 }
 
 #[derive(Debug, Clone)]
 pub struct Score {
     pub bars: Vec<Bar>,
+    pub bpm: Option<i32>, // 谱里给出的速度（ABC 的 Q:），生成时调用 score_setbpm
+    pub meter: Option<(i32, i32)>, // 声明或文件里的拍号，原样的分子分母，例如 6/8；导出乐谱时用
+    pub key: Option<KeySignature>, // 声明或文件里的调号，导出乐谱时用
+}
+
+/// 调式和它的主音比同一个调号的大调主音在五度圈上差几步
+pub const MODES: [(&str, i32); 7] = [
+    ("major", 0),
+    ("minor", -3),
+    ("mixolydian", -1),
+    ("dorian", -2),
+    ("phrygian", -4),
+    ("lydian", 1),
+    ("locrian", -5),
+];
+
+// 调号里升号、降号依次加在哪些音名上
+const SHARPS: [i32; 7] = [3, 0, 4, 1, 5, 2, 6];
+const FLATS: [i32; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+/// 调号：几个升号（降号是负数）和调式，调式是 MODES 里的名字
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeySignature {
+    pub fifths: i32,
+    pub mode: &'static str,
+}
+
+impl KeySignature {
+    /// 以 tonic 为主音的 mode 调式，C 在五度圈上是 0
+    pub fn new(tonic: Spelling, mode: &'static str) -> KeySignature {
+        let shift = MODES.iter().find(|m| m.0 == mode).map_or(0, |m| m.1);
+        KeySignature {
+            fifths: [0, 2, 4, -1, 1, 3, 5][tonic.step as usize] + 7 * tonic.alter + shift,
+            mode,
+        }
+    }
+
+    pub fn tonic(self) -> Spelling {
+        let shift = MODES.iter().find(|m| m.0 == self.mode).map_or(0, |m| m.1);
+        let f = self.fifths - shift + 1; // 从 F 开始数
+        Spelling {
+            step: SHARPS[f.rem_euclid(7) as usize],
+            alter: f.div_euclid(7),
+        }
+    }
+
+    /// 每个音名（C 到 B）在这个调号里的升降
+    pub fn alters(self) -> [i32; 7] {
+        let mut ret = [0; 7];
+        for k in 0..self.fifths.max(0) as usize {
+            ret[SHARPS[k % 7] as usize] += 1;
+        }
+        for k in 0..(-self.fifths).max(0) as usize {
+            ret[FLATS[k % 7] as usize] -= 1;
+        }
+        ret
+    }
+}
+
+// 调号只移动唱名，字母音名是绝对音高
+impl NoteElem {
+    fn transpose_degrees(&mut self, key: Spelling) {
+        match self {
+            NoteElem::Tie | NoteElem::Dynamic(..) => {}
+            NoteElem::Note(note) => note.transpose_degrees(key),
+            NoteElem::Notes(elems) => elems.iter_mut().for_each(|e| e.transpose_degrees(key)),
+            NoteElem::Chord(notes) => notes.iter_mut().for_each(|n| n.transpose_degrees(key)),
+            NoteElem::Scaled(elem, _, _)
+            | NoteElem::Velocity(elem, _)
+            | NoteElem::Articulated(elem, _) => elem.transpose_degrees(key),
+        }
+    }
+}
+
+impl Bar {
+    pub fn transpose_degrees(&mut self, key: Spelling) {
+        self.notes.iter_mut().for_each(|e| e.transpose_degrees(key));
+    }
+}
+
+impl Score {
+    pub fn transpose_degrees(&mut self, key: Spelling) {
+        self.bars.iter_mut().for_each(|b| b.transpose_degrees(key));
+    }
 }
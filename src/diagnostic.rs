@@ -0,0 +1,158 @@
+// 编译错误的收集与输出。
+// 语法树里的节点带着源码中的字节区间（Span），出错时记下一条 Diagnostic，
+// 最后统一按 rustc 的格式打印：错误信息、位置、源码片段和下划线。
+
+use lalrpop_util::ParseError;
+use std::cell::RefCell;
+use std::fmt::Display;
+
+/// 源码中的字节区间 \[lo, hi)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize) -> Span {
+        Span { lo, hi }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// 按 rustc 的格式输出，filename 和 src 用来定位行列号和截取源码
+    pub fn render(&self, filename: &str, src: &str) -> String {
+        let lo = self.span.lo.min(src.len());
+        let hi = self.span.hi.clamp(lo, src.len());
+        let line_start = src[..lo].rfind('\n').map_or(0, |p| p + 1);
+        let line_end = src[lo..].find('\n').map_or(src.len(), |p| lo + p);
+        let line = src[line_start..line_end].trim_end_matches('\r');
+        let line_no = src[..lo].matches('\n').count() + 1;
+        let col = src[line_start..lo].chars().count() + 1;
+        // 跨行的区间只标到第一行末尾
+        let width = std::cmp::max(1, src[lo..std::cmp::min(hi, line_end)].chars().count());
+        let gutter = " ".repeat(line_no.to_string().len());
+        let indent: String = src[line_start..lo]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let mut ret = format!("error: {}\n", self.message);
+        ret.push_str(&format!("{}--> {}:{}:{}\n", gutter, filename, line_no, col));
+        ret.push_str(&format!("{} |\n", gutter));
+        ret.push_str(&format!("{} | {}\n", line_no, line));
+        ret.push_str(&format!("{} | {}{}\n", gutter, indent, "^".repeat(width)));
+        ret
+    }
+}
+
+thread_local! {
+    static DIAGNOSTICS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+}
+
+/// 记下一条错误；生成 IR 时遇到错误不会立刻退出，而是尽量继续往下检查
+pub fn report(diagnostic: Diagnostic) {
+    DIAGNOSTICS.with(|d| d.borrow_mut().push(diagnostic));
+}
+
+/// 取出目前为止记下的所有错误
+pub fn take_diagnostics() -> Vec<Diagnostic> {
+    DIAGNOSTICS.with(|d| std::mem::take(&mut *d.borrow_mut()))
+}
+
+/// 解析整数字面量。十进制的不能超过 i32::MAX（-2147483648 由语法单独处理）；
+/// 十六进制的 s 带 0x 前缀。八进制和十六进制的可以写满 32 位，超过 0x7fffffff 的按补码当作负数，例如 0xffffffff 是 -1
+pub fn parse_int(s: &str, radix: u32, span: Span) -> Result<i32, Diagnostic> {
+    let value = match radix {
+        10 => i32::from_str_radix(s, radix).ok(),
+        16 => u32::from_str_radix(&s[2..], radix).ok().map(|v| v as i32),
+        _ => u32::from_str_radix(s, radix).ok().map(|v| v as i32),
+    };
+    value.ok_or_else(|| Diagnostic::error(span, format!("integer literal `{}` is too large", s)))
+}
+
+/// 乐谱里的时值倍数必须是正数
+pub fn positive_int(n: i32, span: Span) -> Result<i32, Diagnostic> {
+    if n > 0 {
+        Ok(n)
+    } else {
+        Err(Diagnostic::error(span, "duration factor must be positive"))
+    }
+}
+
+/// 解析字符串字面量里的转义字符，span 是整个字面量（含引号）的位置
+pub fn unescape_string(s: &str, span: Span) -> Result<String, Diagnostic> {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => ret.push('\\'),
+            Some('"') => ret.push('"'),
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            other => {
+                let escape = other.map_or(String::new(), |c| c.to_string());
+                return Err(Diagnostic::error(
+                    span,
+                    format!("unknown character escape `\\{}`", escape),
+                ));
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// 把 lalrpop 的解析错误转成 Diagnostic；offset 是被解析的文本在源文件中的起始位置
+pub fn from_parse_error<T: Display>(
+    err: ParseError<usize, T, Diagnostic>,
+    offset: usize,
+) -> Diagnostic {
+    let span = |lo: usize, hi: usize| Span::new(lo + offset, hi + offset);
+    match err {
+        ParseError::InvalidToken { location } => {
+            Diagnostic::error(span(location, location + 1), "invalid token")
+        }
+        ParseError::UnrecognizedEof { location, expected } => Diagnostic::error(
+            span(location, location),
+            format!(
+                "unexpected end of input, expected one of {}",
+                expected.join(", ")
+            ),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (lo, token, hi),
+            expected,
+        } => Diagnostic::error(
+            span(lo, hi),
+            format!(
+                "unexpected `{}`, expected one of {}",
+                token,
+                expected.join(", ")
+            ),
+        ),
+        ParseError::ExtraToken {
+            token: (lo, token, hi),
+        } => Diagnostic::error(span(lo, hi), format!("unexpected `{}`", token)),
+        ParseError::User { error } => {
+            Diagnostic::error(span(error.span.lo, error.span.hi), error.message)
+        }
+    }
+}
@@ -23,6 +23,24 @@ pub fn call_func(
     }
 }
 
+/// 按值传递音乐对象：Bar 和 Score 复制一份新的，Note 不可变、Track 绑定的是文件、Instrument 只能添加采样，直接用原来的句柄
+pub fn gen_music_copy(
+    data: &mut FunctionData,
+    entry: &mut BasicBlock,
+    var: &mut HashMap<String, IdentValue>,
+    ty: MusicType,
+    src: Value,
+) -> Value {
+    let (new, copy) = match ty {
+        MusicType::Bar => ("newbar", "bar_copy"),
+        MusicType::Score => ("newscore", "score_copy"),
+        MusicType::Note | MusicType::Track | MusicType::Instrument => return src,
+    };
+    let it = call_func(new, data, entry, var, vec![]);
+    call_func(copy, data, entry, var, vec![it, src]);
+    it
+}
+
 pub fn gen_note(
     data: &mut FunctionData,
     entry: &mut BasicBlock,
@@ -30,6 +48,7 @@ pub fn gen_note(
     note: Note,
     fz: i32,
     fm: i32,
+    velocity: i32,
 ) -> Value {
     let ffz = data.dfg_mut().new_value().integer(fz);
     let ffm = data.dfg_mut().new_value().integer(fm);
@@ -39,14 +58,70 @@ pub fn gen_note(
             let it = call_func("newnote_rest", data, entry, var, vec![one, ffz, ffm]);
             return it;
         }
-        Note::Semitone(num) => {
+        Note::Semitone(num, _) | Note::Pitch(num, _) => {
             let num = data.dfg_mut().new_value().integer(num.clone());
-            let it = call_func("newnote", data, entry, var, vec![num, ffz, ffm]);
+            let vel = data.dfg_mut().new_value().integer(velocity);
+            let it = call_func("newnote", data, entry, var, vec![num, ffz, ffm, vel]);
             return it;
         }
     }
 }
 
+/// 单个音的力度和演奏法，由外层的 Velocity、Articulated 决定
+#[derive(Clone, Copy)]
+pub struct NoteStyle {
+    pub velocity: i32,
+    pub articulation: i32,
+}
+
+impl Default for NoteStyle {
+    fn default() -> NoteStyle {
+        NoteStyle {
+            velocity: DEFAULT_VELOCITY,
+            articulation: 0,
+        }
+    }
+}
+
+/// 按 {} 的等分和时值后缀把 note 拆成单个的音（音符、延续或和弦），连同各自的时值 fz/fm 和力度、演奏法交给 f。
+/// 力度记号不占时值，不算在 {} 的等分里
+pub fn for_each_note(
+    note: &NoteElem,
+    fz: i32,
+    fm: i32,
+    style: NoteStyle,
+    f: &mut impl FnMut(&NoteElem, i32, i32, NoteStyle),
+) {
+    match note {
+        NoteElem::Scaled(elem, sz, sm) => {
+            let g = gcd(fz * sz, fm * sm);
+            for_each_note(elem, fz * sz / g, fm * sm / g, style, f);
+        }
+        NoteElem::Notes(notes) => {
+            let count = notes.iter().filter(|n| !matches!(***n, NoteElem::Dynamic(..))).count();
+            for note in notes.iter() {
+                for_each_note(note, fz, fm * (count as i32), style, f);
+            }
+        }
+        NoteElem::Velocity(elem, velocity) => {
+            let style = NoteStyle {
+                velocity: *velocity,
+                ..style
+            };
+            for_each_note(elem, fz, fm, style, f);
+        }
+        NoteElem::Articulated(elem, flags) => {
+            let style = NoteStyle {
+                articulation: style.articulation | flags,
+                ..style
+            };
+            for_each_note(elem, fz, fm, style, f);
+        }
+        NoteElem::Dynamic(..) => {}
+        _ => f(note, fz, fm, style),
+    }
+}
+
 pub fn gen_note_in_bar(
     data: &mut FunctionData,
     entry: &mut BasicBlock,
@@ -56,30 +131,63 @@ pub fn gen_note_in_bar(
     fz: i32,
     fm: i32,
 ) {
+    for_each_note(note, fz, fm, NoteStyle::default(), &mut |note, fz, fm, style| {
+        let mut it = gen_single_note(data, entry, var, note, fz, fm, style.velocity);
+        if style.articulation != 0 {
+            let flags = data.dfg_mut().new_value().integer(style.articulation);
+            it = call_func("note_articulate", data, entry, var, vec![it, flags]);
+        }
+        call_func("bar_push", data, entry, var, vec![bar, it]);
+    });
+}
+
+fn gen_single_note(
+    data: &mut FunctionData,
+    entry: &mut BasicBlock,
+    var: &mut HashMap<String, IdentValue>,
+    note: &NoteElem,
+    fz: i32,
+    fm: i32,
+    velocity: i32,
+) -> Value {
     let ffz = data.dfg_mut().new_value().integer(fz);
     let ffm = data.dfg_mut().new_value().integer(fm);
     let one = data.dfg_mut().new_value().integer(1);
     let two = data.dfg_mut().new_value().integer(2);
+    let vel = data.dfg_mut().new_value().integer(velocity);
     match note {
-        NoteElem::Tie => {
-            let it = call_func("newnote_rest", data, entry, var, vec![two, ffz, ffm]);
-            call_func("bar_push", data, entry, var, vec![bar, it]);
-        }
+        NoteElem::Tie => call_func("newnote_rest", data, entry, var, vec![two, ffz, ffm]),
         NoteElem::Note(Note::Rest) => {
-            let it = call_func("newnote_rest", data, entry, var, vec![one, ffz, ffm]);
-            call_func("bar_push", data, entry, var, vec![bar, it]);
+            call_func("newnote_rest", data, entry, var, vec![one, ffz, ffm])
         }
-        NoteElem::Note(Note::Semitone(num)) => {
-            let num = data.dfg_mut().new_value().integer(num.clone());
-            let it = call_func("newnote", data, entry, var, vec![num, ffz, ffm]);
-            call_func("bar_push", data, entry, var, vec![bar, it]);
+        NoteElem::Note(Note::Semitone(num, _) | Note::Pitch(num, _)) => {
+            let num = data.dfg_mut().new_value().integer(*num);
+            call_func("newnote", data, entry, var, vec![num, ffz, ffm, vel])
         }
-        NoteElem::Notes(notes) => {
-            for note in notes.iter() {
-                gen_note_in_bar(data, entry, var, note, bar, fz, fm * (notes.len() as i32));
+        NoteElem::Chord(notes) => {
+            let halves: Vec<i32> = notes.iter().filter_map(Note::semitone).collect();
+            let num = data.dfg_mut().new_value().integer(halves[0]);
+            let mut it = call_func("newnote", data, entry, var, vec![num, ffz, ffm, vel]);
+            for half in halves[1..].iter() {
+                let num = data.dfg_mut().new_value().integer(*half);
+                it = call_func("note_add_pitch", data, entry, var, vec![it, num]);
             }
+            it
         }
+        _ => unreachable!(),
+    }
+}
+
+/// 小节的总拍数，化成最简分数 (fz, fm)；和 gen_note_in_bar 的拆分方式一致，延续音也算拍数
+pub fn bar_beats(bar: &Bar) -> (i32, i32) {
+    let (mut sz, mut sm) = (0, 1);
+    for note in bar.notes.iter() {
+        for_each_note(note, 1, 1, NoteStyle::default(), &mut |_, fz, fm, _| {
+            let g = gcd(sz * fm + fz * sm, sm * fm);
+            (sz, sm) = ((sz * fm + fz * sm) / g, sm * fm / g);
+        });
     }
+    (sz, sm)
 }
 
 pub fn gen_bar(
@@ -109,9 +217,21 @@ pub fn gen_score(
         // bar 内自己处理
         // bar 间 imp.cpp 处理
     }
+    if let Some(bpm) = score.bpm {
+        let bpm = data.dfg_mut().new_value().integer(bpm);
+        call_func("score_setbpm", data, entry, var, vec![it, bpm]);
+    }
     return it;
 }
 
+pub fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
 pub fn count_semitone(x: i32) -> i32 {
     if x == 1 {
         return 0;
@@ -0,0 +1,100 @@
+// 运行时库（libsysylive.a）的函数签名表。
+// 编译器用它生成 decl，--emit-runtime-header 用它生成 C 头文件，
+// build.rs 用它检查 runtime.rs 导出的函数签名，三者不会再对不上。
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LibType {
+    Unit,
+    Int,
+    IntPtr,
+}
+
+pub struct LibFunc {
+    pub name: &'static str,
+    pub ret: LibType,
+    pub params: &'static [LibType],
+}
+
+use LibType::*;
+
+const fn libfunc(name: &'static str, ret: LibType, params: &'static [LibType]) -> LibFunc {
+    LibFunc { name, ret, params }
+}
+
+pub const LIBFUNCS: &[LibFunc] = &[
+    libfunc("getint", Int, &[]),
+    libfunc("getch", Int, &[]),
+    libfunc("getarray", Int, &[IntPtr]),
+    libfunc("putint", Unit, &[Int]),
+    libfunc("putch", Unit, &[Int]),
+    libfunc("putarray", Unit, &[Int, IntPtr]),
+    libfunc("starttime", Unit, &[]),
+    libfunc("stoptime", Unit, &[]),
+    // now comes the music decls
+    libfunc("track_copy", Unit, &[Int, IntPtr, IntPtr]),
+    libfunc("track_load", Unit, &[Int, IntPtr]),
+    libfunc("score_sing", Unit, &[Int, IntPtr, IntPtr, Int, Int, Int]),
+    libfunc("score_sing_preset", Unit, &[Int, IntPtr, Int, IntPtr, Int, Int, Int]),
+    libfunc("score_sing_instrument", Unit, &[Int, Int, IntPtr, Int, Int, Int]),
+    libfunc("score_export_midi", Unit, &[Int, IntPtr]),
+    libfunc("newinstrument", Int, &[]),
+    libfunc("instrument_add_sample", Unit, &[Int, IntPtr, Int]),
+    libfunc("score_push", Unit, &[Int, Int]),
+    libfunc("bar_push", Unit, &[Int, Int]),
+    libfunc("newnote", Int, &[Int, Int, Int, Int]),
+    libfunc("newnote_rest", Int, &[Int, Int, Int]),
+    libfunc("note_add_pitch", Int, &[Int, Int]),
+    libfunc("note_articulate", Int, &[Int, Int]),
+    libfunc("newbar", Int, &[]),
+    libfunc("newscore", Int, &[]),
+    libfunc("newtrack", Int, &[]),
+    libfunc("bar_setbpm", Unit, &[Int, Int]),
+    libfunc("score_setbpm", Unit, &[Int, Int]),
+    libfunc("score_append", Unit, &[Int, Int]),
+    libfunc("bar_copy", Unit, &[Int, Int]),
+    libfunc("score_copy", Unit, &[Int, Int]),
+    libfunc("score_replace", Unit, &[Int, Int, Int]),
+    libfunc("score_inc_pitch", Unit, &[Int, Int]),
+    libfunc("bar_inc_pitch", Unit, &[Int, Int]),
+    libfunc("score_set_duration", Unit, &[Int, Int]),
+    libfunc("score_set_envelope", Unit, &[Int, Int, Int, Int, Int]),
+    libfunc("bar_set_duration", Unit, &[Int, Int]),
+    libfunc("track_append", Unit, &[Int, Int]),
+    libfunc("track_stack", Unit, &[Int, Int]),
+    libfunc("track_stack_at", Unit, &[Int, Int, Int]),
+    libfunc("track_pan", Unit, &[Int, Int]),
+    libfunc("track_gain_db", Unit, &[Int, Int]),
+    libfunc("track_set_volume", Unit, &[Int, Int, Int]),
+    libfunc("track_append_silence", Unit, &[Int, Int, Int, Int, Int]),
+];
+
+impl LibType {
+    pub fn c_type(self) -> &'static str {
+        match self {
+            Unit => "void",
+            Int => "int",
+            IntPtr => "int *",
+        }
+    }
+}
+
+/// 生成运行时库的 C 头文件
+pub fn c_header() -> String {
+    let mut ret = String::new();
+    ret.push_str("/* generated by `compiler --emit-runtime-header`, do not edit */\n");
+    ret.push_str("#ifndef SYSYLIVE_H\n#define SYSYLIVE_H\n\n");
+    ret.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    for func in LIBFUNCS.iter() {
+        let params: Vec<&str> = func.params.iter().map(|p| p.c_type()).collect();
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        let ret_type = func.ret.c_type();
+        let sep = if ret_type.ends_with('*') { "" } else { " " };
+        ret.push_str(&format!("{}{}{}({});\n", ret_type, sep, func.name, params));
+    }
+    ret.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+    ret
+}
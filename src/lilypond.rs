@@ -0,0 +1,243 @@
+// --emit lilypond：把程序里的 Score 字面量写成 LilyPond 的 .ly 文件，每个 Score 一个 \score。
+// 音高用绝对八度写法（c' 是 C4），升降号按谱里原来的 b、# 写法，{} 里不是 2 的幂个音时写成 \tuplet。
+// 调号和拍号用声明里（或 ABC 的 K:、M:）给的，没有给时不写调号、拍号按小节长度推断。
+
+use crate::ast::*;
+use crate::gen_music::gcd;
+use crate::sheet::*;
+use std::fmt::Write;
+
+fn dynamic_mark(dynamic: &Dynamic) -> &'static str {
+    match dynamic {
+        Dynamic::Level(..=24) => "\\ppp",
+        Dynamic::Level(25..=40) => "\\pp",
+        Dynamic::Level(41..=56) => "\\p",
+        Dynamic::Level(57..=72) => "\\mp",
+        Dynamic::Level(73..=88) => "\\mf",
+        Dynamic::Level(89..=104) => "\\f",
+        Dynamic::Level(105..=119) => "\\ff",
+        Dynamic::Level(_) => "\\fff",
+        Dynamic::Cresc => "\\<",
+        Dynamic::Dim => "\\>",
+    }
+}
+
+/// 不带八度的音名，例如 Bb 是 bes
+fn step_name(spelling: Spelling) -> String {
+    let mut ret = "cdefgab"[spelling.step as usize..][..1].to_string();
+    for _ in 0..spelling.alter.abs() {
+        ret.push_str(if spelling.alter > 0 { "is" } else { "es" });
+    }
+    ret
+}
+
+/// 音名加八度记号，例如 C4 是 c'，Bb3 是 bes，F#5 是 fis''
+fn pitch_name(half: i32, spelling: Spelling) -> String {
+    let mut ret = step_name(spelling);
+    let octave = spelling.octave(half) - 3;
+    let mark = if octave > 0 { "'" } else { "," };
+    ret.push_str(&mark.repeat(octave.unsigned_abs() as usize));
+    ret
+}
+
+/// 记谱时值 (fz, fm) 拍写成 LilyPond 的时值，写不成一个音符时拆成几个用延音线连起来的音符。
+/// 拆不开（比 64 分音符还细）时用 4*fz/fm 这样的缩放写法
+fn durations(written: (i32, i32)) -> Vec<String> {
+    if let Some((value, dots)) = note_value(written) {
+        return vec![format!("{}{}", value, ".".repeat(dots))];
+    }
+    let mut ret = Vec::new();
+    let (mut fz, mut fm) = written;
+    // 从全音符（4 拍）开始，每次取不超过剩下时值的最长音符
+    let mut value = 1;
+    while fz > 0 && value <= 64 {
+        if fz * value >= 4 * fm {
+            ret.push(value.to_string());
+            let (z, m) = (fz * value - 4 * fm, fm * value);
+            let g = gcd(z, m);
+            (fz, fm) = (z / g, m / g);
+        } else {
+            value *= 2;
+        }
+    }
+    if fz > 0 {
+        let (z, m) = written;
+        return vec![format!("4*{}/{}", z, m)];
+    }
+    ret
+}
+
+/// 小节里用延音线连起来的几个音，合起来能写成一个音符时就写成一个，例如 5 - - - 是 g'1。
+/// 连音里的音和带力度记号的音不合并
+fn merge_ties(notes: &[SheetNote]) -> Vec<SheetNote> {
+    let plain = |note: &SheetNote| note.tuplet == (1, 1) && note.tuplet_starts.is_empty();
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < notes.len() {
+        let mut merged = notes[i].clone();
+        let mut next = i + 1;
+        if plain(&notes[i]) {
+            let (mut fz, mut fm) = notes[i].duration;
+            let mut j = i + 1;
+            while j < notes.len()
+                && notes[j - 1].tie_start
+                && notes[j].tie_stop
+                && plain(&notes[j])
+                && notes[j].dynamics.is_empty()
+            {
+                let (z, m) = notes[j].duration;
+                let g = gcd(fz * m + z * fm, fm * m);
+                (fz, fm) = ((fz * m + z * fm) / g, fm * m / g);
+                if note_value((fz, fm)).is_some() {
+                    merged.duration = (fz, fm);
+                    merged.tie_start = notes[j].tie_start;
+                    next = j + 1;
+                }
+                j += 1;
+            }
+        }
+        ret.push(merged);
+        i = next;
+    }
+    ret
+}
+
+fn write_note(out: &mut String, note: &SheetNote) {
+    for &(count, normal) in note.tuplet_starts.iter() {
+        let _ = write!(out, "\\tuplet {}/{} {{ ", count, normal);
+    }
+    let pitch = match note.pitches.len() {
+        0 => "r".to_string(),
+        1 => pitch_name(note.pitches[0].0, note.pitches[0].1),
+        _ => {
+            let names: Vec<String> = note
+                .pitches
+                .iter()
+                .map(|&(half, spelling)| pitch_name(half, spelling))
+                .collect();
+            format!("<{}>", names.join(" "))
+        }
+    };
+    let pieces = durations(note.written());
+    for (i, duration) in pieces.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let _ = write!(out, "{}{}", pitch, duration);
+        // 演奏法和力度记号只写在第一个音上
+        if i == 0 {
+            for (flag, mark) in [
+                (STACCATO, "-."),
+                (TENUTO, "--"),
+                (ACCENT, "->"),
+                (FERMATA, "\\fermata"),
+            ] {
+                if note.articulation & flag != 0 {
+                    out.push_str(mark);
+                }
+            }
+            for dynamic in note.dynamics.iter() {
+                out.push_str(dynamic_mark(dynamic));
+            }
+        }
+        if !note.pitches.is_empty() && (i + 1 < pieces.len() || note.tie_start) {
+            out.push('~');
+        }
+    }
+    for _ in 0..note.tuplet_stops {
+        out.push_str(" }");
+    }
+    out.push(' ');
+}
+
+fn write_score(out: &mut String, name: &str, score: &Score) {
+    out.push_str("\\score {\n  \\new Staff {\n");
+    if let Some(bpm) = score.bpm {
+        let _ = writeln!(out, "    \\tempo 4 = {}", bpm);
+    }
+    if let Some(key) = score.key {
+        let _ = writeln!(out, "    \\key {} \\{}", step_name(key.tonic()), key.mode);
+    }
+    for bar in sheet_bars(score).iter() {
+        out.push_str("    ");
+        if let Some((num, den)) = bar.time {
+            let _ = write!(out, "\\time {}/{} ", num, den);
+        }
+        if bar.pickup {
+            let (fz, fm) = bar.beats;
+            match note_value(bar.beats) {
+                Some((value, dots)) => {
+                    let _ = write!(out, "\\partial {}{} ", value, ".".repeat(dots));
+                }
+                None => {
+                    let _ = write!(out, "\\partial 4*{}/{} ", fz, fm);
+                }
+            }
+        }
+        for note in merge_ties(&bar.notes).iter() {
+            write_note(out, note);
+        }
+        out.push_str("|\n");
+    }
+    out.push_str("  }\n");
+    let _ = writeln!(out, "  \\header {{ piece = \"{}\" }}", name);
+    out.push_str("  \\layout { }\n}\n");
+}
+
+/// 所有 Score 字面量的 LilyPond 源文件；没有 Score 字面量时返回 None
+pub fn lilypond(ast: &CompUnit) -> Option<String> {
+    let scores = score_literals(ast);
+    if scores.is_empty() {
+        return None;
+    }
+    let mut out = String::from("\\version \"2.24.0\"\n");
+    for (name, score) in scores.iter() {
+        out.push('\n');
+        write_score(&mut out, name, score);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysy::CompUnitParser;
+    use std::cell::RefCell;
+
+    fn export(decls: &str) -> String {
+        let strings = RefCell::new(Vec::new());
+        let src = format!("int main() {{ {} return 0; }}", decls);
+        let ast = CompUnitParser::new().parse(&strings, &src).unwrap();
+        lilypond(&ast).unwrap()
+    }
+
+    #[test]
+    fn merged_ties() {
+        let out = export("Score s(syllablename = \"5 - - - | 1 - 2 - | 3 - - 4 | 6 - - - - 5\");");
+        assert!(out.contains("g'1 |"));
+        assert!(out.contains("c'2 d'2 |"));
+        assert!(out.contains("e'2. f'4 |"));
+        // 五拍写不成一个音符，合并成最长的全音符，剩下的一拍单独写
+        assert!(out.contains("a'1~ a'4 g'4 |"));
+    }
+
+    #[test]
+    fn declared_meter_and_key() {
+        let out =
+            export("Score s(syllablename = \"1 2 3 | 5 - -\", meter = \"6/8\", key = \"Bb\");");
+        assert!(out.contains("\\key bes \\major"));
+        assert!(out.contains("\\time 6/8 bes'4 c''4 d''4 |"));
+        assert_eq!(out.matches("\\time").count(), 1);
+    }
+
+    #[test]
+    fn pickup_and_short_last_bar() {
+        let out = export("Score s(abc = \"X:1\\nM:3/4\\nL:1/4\\nK:Ador\\nE | A B c | e2 |]\");");
+        assert!(out.contains("\\key a \\dorian"));
+        assert!(out.contains("\\time 3/4 \\partial 4 e'4 |"));
+        assert_eq!(out.matches("\\time").count(), 1);
+        let out = export("Score s(syllablename = \"0 1 | 2 3 4\");");
+        assert!(out.contains("\\time 3/4 \\partial 2 r4 c'4 |"));
+        assert!(!out.contains("\\key"));
+    }
+}
@@ -2,8 +2,10 @@
 use koopa::back::KoopaGenerator;
 use koopa::back::LlvmGenerator;
 use koopa::ir::*;
+use diagnostic::*;
 use lalrpop_util::lalrpop_mod;
 use optimize_loadstore::OptimizeLoadStore;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::env;
 use std::fs::read_to_string;
@@ -11,28 +13,89 @@ use std::io::Result;
 
 lalrpop_mod!(sysy);
 
+mod abc_import;
 mod arrayinit;
+mod articulation;
 mod ast;
 mod constint;
+mod diagnostic;
 mod ident;
+mod libfuncs;
+mod lilypond;
+mod midi_import;
+mod musicxml;
+mod notation;
 // mod optimize_exp;
 mod optimize_loadstore;
 mod riscv;
+mod sheet;
 mod tokoopa;
+mod typecheck;
 mod whilecontext;
 mod gen_music;
 
 fn main() -> Result<()> {
     Type::set_ptr_size(4);
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
     // println!("{:?}", args);
+    if args.len() == 4 && args[1] == "--emit-runtime-header" && args[2] == "-o" {
+        std::fs::write(args[3].clone(), libfuncs::c_header())?;
+        return Ok(());
+    }
+    // compiler --emit musicxml 输入文件 -o 输出文件：不编译，只把谱导出，之后 args[1] 就是格式
+    let emit = args.len() == 6 && args[1] == "--emit";
+    if emit {
+        args.remove(1);
+        if args[1] != "musicxml" && args[1] != "lilypond" {
+            eprintln!(
+                "error: unknown format `{}`, expected `musicxml` or `lilypond`",
+                args[1]
+            );
+            std::process::exit(1);
+        }
+    }
     if args.len() != 5 || args[3] != "-o" {
         println!("usage: compiler 阶段 输入文件 -o 输出文件");
+        println!("       compiler --emit musicxml|lilypond 输入文件 -o 输出文件");
+        println!("       compiler --emit-runtime-header -o 输出文件");
         return Ok(());
     }
     let input = read_to_string(args[2].clone())?;
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
+    let strings = RefCell::new(Vec::new());
+    let mut ast = match sysy::CompUnitParser::new().parse(&strings, &input) {
+        Ok(ast) => ast,
+        Err(err) => {
+            // 解析过程中已经记下的错误（例如小节拍数不对）也一起输出
+            let mut diagnostics = take_diagnostics();
+            diagnostics.push(from_parse_error(err, 0));
+            exit_with_diagnostics(&args[2], &input, diagnostics)
+        }
+    };
+    ast.typecheck();
+    let diagnostics = take_diagnostics();
+    if !diagnostics.is_empty() {
+        exit_with_diagnostics(&args[2], &input, diagnostics);
+    }
+    if emit {
+        let text = if args[1] == "lilypond" {
+            lilypond::lilypond(&ast)
+        } else {
+            musicxml::musicxml(&ast)
+        };
+        match text {
+            Some(text) => std::fs::write(args[4].clone(), text)?,
+            None => {
+                eprintln!("error: `{}` has no Score literal to export", args[2]);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
     let mut program = ast.gen_ir();
+    let diagnostics = take_diagnostics();
+    if !diagnostics.is_empty() {
+        exit_with_diagnostics(&args[2], &input, diagnostics);
+    }
 
     // here comes the optimizations
     // loop {
@@ -64,3 +127,12 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+/// 打印所有错误后以非零状态退出
+fn exit_with_diagnostics(filename: &str, src: &str, diagnostics: Vec<Diagnostic>) -> ! {
+    for d in diagnostics.iter() {
+        eprint!("{}", d.render(filename, src));
+    }
+    eprintln!("error: aborting due to {} previous error(s)", diagnostics.len());
+    std::process::exit(1);
+}
@@ -0,0 +1,132 @@
+// 标准 MIDI 文件（SMF）。导出时写 Type 1：第 0 轨是速度轨，第 1 轨是音符。
+
+use std::fs;
+use std::io::Result;
+
+/// 每个四分音符（一拍）的 tick 数
+pub const PPQ: u32 = 480;
+
+/// 一个音符，start 和 end 以 tick 为单位，key 是 MIDI 音高（C4 = 60）
+pub struct MidiNote {
+    pub start: u32,
+    pub end: u32,
+    pub key: u8,
+    pub velocity: u8,
+}
+
+fn push_var_len(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+/// 把 (tick, 事件内容) 按时间编码成一个 MTrk 块，末尾加上 End of Track
+fn track_chunk(mut events: Vec<(u32, Vec<u8>)>) -> Vec<u8> {
+    events.sort_by_key(|(tick, _)| *tick);
+    let mut body = Vec::new();
+    let mut last = 0;
+    for (tick, event) in events.iter() {
+        push_var_len(&mut body, tick - last);
+        body.extend(event);
+        last = *tick;
+    }
+    body.extend([0x00, 0xff, 0x2f, 0x00]);
+    let mut chunk = b"MTrk".to_vec();
+    chunk.extend((body.len() as u32).to_be_bytes());
+    chunk.extend(body);
+    chunk
+}
+
+/// tempos 是 (tick, bpm) 的列表，notes 放在第 1 轨的第 0 通道
+pub fn write_smf(path: &str, tempos: &[(u32, i32)], notes: &[MidiNote]) -> Result<()> {
+    let tempo_events = tempos
+        .iter()
+        .map(|&(tick, bpm)| {
+            let us = (60_000_000 / bpm.max(1) as u32).min(0xff_ffff);
+            (tick, vec![0xff, 0x51, 0x03, (us >> 16) as u8, (us >> 8) as u8, us as u8])
+        })
+        .collect();
+    // 同一时刻先关再开，否则重复的音会被立刻关掉
+    let mut note_events = Vec::new();
+    for note in notes.iter() {
+        note_events.push((note.end, vec![0x80, note.key, 0]));
+    }
+    for note in notes.iter() {
+        note_events.push((note.start, vec![0x90, note.key, note.velocity]));
+    }
+    let mut out = b"MThd".to_vec();
+    out.extend(6u32.to_be_bytes());
+    out.extend(1u16.to_be_bytes());
+    out.extend(2u16.to_be_bytes());
+    out.extend((PPQ as u16).to_be_bytes());
+    out.extend(track_chunk(tempo_events));
+    out.extend(track_chunk(note_events));
+    fs::write(path, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn var_len() {
+        for (value, bytes) in [
+            (0, vec![0x00]),
+            (0x7f, vec![0x7f]),
+            (0x80, vec![0x81, 0x00]),
+            (0x3fff, vec![0xff, 0x7f]),
+            (0x20_0000, vec![0x81, 0x80, 0x80, 0x00]),
+        ] {
+            let mut out = Vec::new();
+            push_var_len(&mut out, value);
+            assert_eq!(out, bytes, "{:#x}", value);
+        }
+    }
+
+    #[test]
+    fn repeated_note_is_released_first() {
+        let path = std::env::temp_dir().join("sysylive_midi_repeat.mid");
+        let path = path.to_str().unwrap();
+        let notes = [(0, PPQ), (PPQ, 2 * PPQ)].map(|(start, end)| MidiNote {
+            start,
+            end,
+            key: 60,
+            velocity: 80,
+        });
+        write_smf(path, &[], &notes).unwrap();
+        let data = fs::read(path).unwrap();
+        let _ = fs::remove_file(path);
+        // 速度轨只有 End of Track，后面是音符轨
+        let track = &data[14 + 12..];
+        assert_eq!(&track[..8], b"MTrk\0\0\0\x16");
+        let body = [
+            0x00, 0x90, 60, 80, // 第 0 tick 按下
+            0x83, 0x60, 0x80, 60, 0, // 480 tick 后先松开
+            0x00, 0x90, 60, 80, // 再按下
+            0x83, 0x60, 0x80, 60, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        assert_eq!(&track[8..], &body);
+    }
+
+    #[test]
+    fn write_header_and_tempo() {
+        let path = std::env::temp_dir().join("sysylive_midi_test.mid");
+        let path = path.to_str().unwrap();
+        let note = MidiNote {
+            start: 0,
+            end: PPQ,
+            key: 60,
+            velocity: 100,
+        };
+        write_smf(path, &[(0, 120)], &[note]).unwrap();
+        let data = fs::read(path).unwrap();
+        let _ = fs::remove_file(path);
+        assert_eq!(&data[..14], b"MThd\0\0\0\x06\0\x01\0\x02\x01\xe0");
+        assert_eq!(&data[22..29], &[0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20]);
+    }
+}
@@ -0,0 +1,390 @@
+// 读取标准 MIDI 文件，生成和 Score x(syllablename = "...") 一样的 Score。
+// 音符的起止按 GRID 量化，按拍号事件切分小节；同时开始的音合成和弦，
+// 后一个音开始时前面还在响的音就截断，所以复调的声部会被并成一条旋律线。
+
+use crate::ast::*;
+use crate::diagnostic::Span;
+use crate::gen_music::gcd;
+use std::fs;
+
+/// 量化的精度：每拍分成 GRID 份，能表示十六分音符和三连音
+const GRID: i64 = 12;
+
+struct RawNote {
+    start: i64, // 以 tick 为单位
+    end: i64,
+    key: i32,
+    velocity: i32,
+}
+
+struct Smf {
+    division: i64,                // 每拍的 tick 数
+    meters: Vec<(i64, i64, i64)>, // 拍号事件 (tick, 分子, 分母)
+    tempos: Vec<(i64, i64)>,      // 速度事件 (tick, 每拍的微秒数)
+    keys: Vec<(i64, i32, bool)>,  // 调号事件 (tick, 升降号个数, 是否小调)
+    tracks: Vec<Vec<RawNote>>,
+}
+
+fn read_var_len(data: &[u8], pos: &mut usize) -> Result<i64, String> {
+    let mut value = 0;
+    for _ in 0..4 {
+        let byte = *data.get(*pos).ok_or("unexpected end of track")?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7f) as i64;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err("invalid variable-length quantity".to_string())
+}
+
+fn read_track(data: &[u8], smf: &mut Smf) -> Result<(), String> {
+    let mut notes = Vec::new();
+    // 还没有松开的音：(通道, 音高, 开始, 力度)
+    let mut pending: Vec<(u8, u8, i64, i32)> = Vec::new();
+    let mut pos = 0;
+    let mut tick = 0;
+    let mut status = 0u8;
+    while pos < data.len() {
+        tick += read_var_len(data, &mut pos)?;
+        let byte = *data.get(pos).ok_or("unexpected end of track")?;
+        if byte >= 0x80 {
+            status = byte;
+            pos += 1;
+        } else if status < 0x80 {
+            return Err("running status without a previous event".to_string());
+        }
+        match status {
+            0xff => {
+                let kind = *data.get(pos).ok_or("unexpected end of track")?;
+                pos += 1;
+                let len = read_var_len(data, &mut pos)? as usize;
+                let body = data.get(pos..pos + len).ok_or("unexpected end of track")?;
+                pos += len;
+                if kind == 0x58 && len >= 2 {
+                    smf.meters.push((tick, body[0] as i64, 1 << body[1].min(6)));
+                }
+                if kind == 0x59 && len >= 2 {
+                    smf.keys.push((tick, body[0] as i8 as i32, body[1] == 1));
+                }
+                if kind == 0x51 && len >= 3 {
+                    let tempo = (body[0] as i64) << 16 | (body[1] as i64) << 8 | body[2] as i64;
+                    smf.tempos.push((tick, tempo));
+                }
+                if kind == 0x2f {
+                    break;
+                }
+                status = 0;
+            }
+            0xf0 | 0xf7 => {
+                pos += read_var_len(data, &mut pos)? as usize;
+                status = 0;
+            }
+            _ => {
+                let len = if matches!(status & 0xf0, 0xc0 | 0xd0) {
+                    1
+                } else {
+                    2
+                };
+                let args = data.get(pos..pos + len).ok_or("unexpected end of track")?;
+                pos += len;
+                let channel = status & 0x0f;
+                let kind = status & 0xf0;
+                if kind == 0x90 && args[1] > 0 {
+                    pending.push((channel, args[0], tick, args[1] as i32));
+                } else if kind == 0x80 || kind == 0x90 {
+                    if let Some(i) = pending
+                        .iter()
+                        .position(|p| p.0 == channel && p.1 == args[0])
+                    {
+                        let (_, key, start, velocity) = pending.remove(i);
+                        notes.push(RawNote {
+                            start,
+                            end: tick,
+                            key: key as i32,
+                            velocity,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    // 没有松开的音持续到轨道结束
+    for (_, key, start, velocity) in pending {
+        notes.push(RawNote {
+            start,
+            end: tick,
+            key: key as i32,
+            velocity,
+        });
+    }
+    smf.tracks.push(notes);
+    Ok(())
+}
+
+fn read_smf(data: &[u8]) -> Result<Smf, String> {
+    if data.len() < 14 || &data[0..4] != b"MThd" {
+        return Err("not a Standard MIDI File".to_string());
+    }
+    let division = u16::from_be_bytes([data[12], data[13]]) as i64;
+    if division & 0x8000 != 0 || division == 0 {
+        return Err("SMPTE time division is not supported".to_string());
+    }
+    let mut smf = Smf {
+        division,
+        meters: Vec::new(),
+        tempos: Vec::new(),
+        keys: Vec::new(),
+        tracks: Vec::new(),
+    };
+    let mut pos = 8 + u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let body = data.get(pos + 8..pos + 8 + len).ok_or("truncated chunk")?;
+        if &data[pos..pos + 4] == b"MTrk" {
+            read_track(body, &mut smf)?;
+        }
+        pos += 8 + len;
+    }
+    smf.meters.sort_by_key(|m| m.0);
+    smf.tempos.sort_by_key(|t| t.0);
+    smf.keys.sort_by_key(|k| k.0);
+    Ok(smf)
+}
+
+/// 长 units 个 GRID 的元素
+fn scaled(elem: NoteElem, units: i64) -> NoteElem {
+    let g = gcd(units as i32, GRID as i32);
+    NoteElem::Scaled(Box::new(elem), units as i32 / g, GRID as i32 / g)
+}
+
+/// 读取 path 里的第 track 条轨道（从 0 开始；None 时取第一条有音符的轨道）。
+/// meter 是声明里写的拍号，给了就不用文件里的拍号事件
+pub fn import_midi(
+    path: &str,
+    track: Option<usize>,
+    meter: Option<(i32, i32)>,
+    span: Span,
+) -> Result<Score, String> {
+    let data = fs::read(path).map_err(|e| format!("cannot read MIDI file `{}`: {}", path, e))?;
+    let smf = read_smf(&data).map_err(|e| format!("invalid MIDI file `{}`: {}", path, e))?;
+    let index = match track {
+        Some(index) if index < smf.tracks.len() => index,
+        Some(index) => {
+            return Err(format!(
+                "MIDI file `{}` has no track {} (it has {} tracks)",
+                path,
+                index,
+                smf.tracks.len()
+            ))
+        }
+        None => smf.tracks.iter().position(|t| !t.is_empty()).unwrap_or(0),
+    };
+    let quantize = |tick: i64| (tick * GRID + smf.division / 2) / smf.division;
+    let mut notes: Vec<(i64, i64, i32, i32)> = smf.tracks[index]
+        .iter()
+        .map(|n| {
+            let start = quantize(n.start);
+            (start, quantize(n.end).max(start + 1), n.key, n.velocity)
+        })
+        .collect();
+    if notes.is_empty() {
+        return Err(format!(
+            "track {} of MIDI file `{}` has no notes",
+            index, path
+        ));
+    }
+    notes.sort();
+    // 以 GRID 为单位的片段：(开始, 结束, 同时开始的音, 力度)
+    let mut segments: Vec<(i64, i64, Vec<i32>, i32)> = Vec::new();
+    let mut i = 0;
+    let mut cursor = 0;
+    while i < notes.len() {
+        let start = notes[i].0;
+        let mut group = Vec::new();
+        while i < notes.len() && notes[i].0 == start {
+            group.push(notes[i]);
+            i += 1;
+        }
+        if start > cursor {
+            segments.push((cursor, start, Vec::new(), 0));
+        }
+        let mut end = group.iter().map(|n| n.1).max().unwrap();
+        if i < notes.len() {
+            end = end.min(notes[i].0);
+        }
+        let mut keys: Vec<i32> = group.iter().map(|n| n.2).collect();
+        keys.sort();
+        keys.dedup();
+        let velocity = group.iter().map(|n| n.3).max().unwrap();
+        segments.push((start, end, keys, velocity));
+        cursor = end;
+    }
+
+    let bar_units = |at: i64| -> i64 {
+        let (num, den) = match meter {
+            Some((fz, fm)) => (fz as i64, fm as i64),
+            None => smf
+                .meters
+                .iter()
+                .rev()
+                .find(|m| quantize(m.0) <= at)
+                .map_or((4, 4), |m| (m.1, m.2)),
+        };
+        (num * 4 * GRID / den).max(1)
+    };
+    let mut bars = Vec::new();
+    let mut bar = Vec::new();
+    let mut bar_end = bar_units(0);
+    for (start, end, keys, velocity) in segments {
+        let mut at = start;
+        while at < end {
+            let len = end.min(bar_end) - at;
+            let elem = if keys.is_empty() {
+                NoteElem::Note(Note::Rest)
+            } else if at > start {
+                NoteElem::Tie
+            } else if keys.len() == 1 {
+                NoteElem::Velocity(
+                    Box::new(NoteElem::Note(Note::Pitch(keys[0] - 60, Spelling::of(keys[0])))),
+                    velocity,
+                )
+            } else {
+                let chord = keys.iter().map(|k| Note::Pitch(k - 60, Spelling::of(*k))).collect();
+                NoteElem::Velocity(Box::new(NoteElem::Chord(chord)), velocity)
+            };
+            bar.push(scaled(elem, len));
+            at += len;
+            if at == bar_end {
+                bars.push(Bar {
+                    notes: std::mem::take(&mut bar),
+                    span,
+                });
+                bar_end += bar_units(bar_end);
+            }
+        }
+    }
+    if !bar.is_empty() {
+        bars.push(Bar { notes: bar, span });
+    }
+    // Score 只有一个速度，用文件里最早的速度事件；没有速度事件时用 Score 的默认速度
+    let bpm = smf
+        .tempos
+        .first()
+        .filter(|t| t.1 > 0)
+        .map(|t| ((60_000_000 + t.1 / 2) / t.1) as i32);
+    // 拍号和调号同样只记开头的，导出乐谱时用
+    let meter = meter.or_else(|| {
+        smf.meters
+            .first()
+            .filter(|m| m.0 == 0)
+            .map(|m| (m.1 as i32, m.2 as i32))
+    });
+    let key = smf.keys.first().filter(|k| k.0 == 0).map(|k| KeySignature {
+        fifths: k.1,
+        mode: if k.2 { "minor" } else { "major" },
+    });
+    Ok(Score {
+        bars,
+        bpm,
+        meter,
+        key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = kind.to_vec();
+        out.extend((body.len() as u32).to_be_bytes());
+        out.extend(body);
+        out
+    }
+
+    fn smf(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut out = chunk(b"MThd", &[0, 1, 0, tracks.len() as u8, 0, 96]);
+        for track in tracks {
+            out.extend(chunk(b"MTrk", track));
+        }
+        out
+    }
+
+    fn import(name: &str, data: &[u8]) -> Result<Score, String> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, data).unwrap();
+        let score = import_midi(path.to_str().unwrap(), None, None, Span::default());
+        let _ = fs::remove_file(&path);
+        score
+    }
+
+    #[test]
+    fn tempo_meter_and_chords() {
+        // 速度轨：3/4 拍，E 小调（一个升号），每拍 428571 微秒（140 bpm）
+        let conductor: &[u8] = &[
+            0x00, 0xff, 0x58, 0x04, 3, 2, 24, 8, //
+            0x00, 0xff, 0x59, 0x02, 1, 1, //
+            0x00, 0xff, 0x51, 0x03, 0x06, 0x8a, 0x1b, //
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        // C4 和 E4 同时响一拍，然后用 running status 写 G4 三拍
+        let notes: &[u8] = &[
+            0x00, 0x90, 60, 100, 0x00, 64, 100, //
+            0x60, 0x80, 60, 0, 0x00, 64, 0, //
+            0x00, 0x90, 67, 90, //
+            0x82, 0x20, 67, 0, //
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let score = import("sysylive_import_tempo.mid", &smf(&[conductor, notes])).unwrap();
+        assert_eq!(score.bpm, Some(140));
+        assert_eq!(score.meter, Some((3, 4)));
+        assert_eq!(
+            score.key,
+            Some(KeySignature {
+                fifths: 1,
+                mode: "minor"
+            })
+        );
+        assert_eq!(score.bars.len(), 2);
+        let NoteElem::Scaled(first, 1, 1) = &score.bars[0].notes[0] else {
+            panic!("{:?}", score.bars[0].notes[0]);
+        };
+        assert!(matches!(first.as_ref(), NoteElem::Velocity(chord, 100)
+            if matches!(chord.as_ref(), NoteElem::Chord(notes) if notes.len() == 2)));
+        // G4 跨过小节线，第二小节开头是延音
+        assert!(matches!(&score.bars[1].notes[0], NoteElem::Scaled(tie, 1, 1)
+            if matches!(tie.as_ref(), NoteElem::Tie)));
+    }
+
+    #[test]
+    fn truncated_track() {
+        let mut data = smf(&[&[0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00]]);
+        data.truncate(data.len() - 4);
+        assert_eq!(read_smf(&data).err().unwrap(), "truncated chunk");
+        // 块的长度对，但最后一个事件不完整
+        let data = smf(&[&[0x00, 0x90, 60, 100, 0x60, 0x80, 60]]);
+        assert_eq!(read_smf(&data).err().unwrap(), "unexpected end of track");
+    }
+
+    #[test]
+    fn malformed_events() {
+        let data = smf(&[&[0x00, 60, 100]]);
+        assert_eq!(
+            read_smf(&data).err().unwrap(),
+            "running status without a previous event"
+        );
+        let data = smf(&[&[0xff, 0xff, 0xff, 0xff, 0x7f]]);
+        assert_eq!(
+            read_smf(&data).err().unwrap(),
+            "invalid variable-length quantity"
+        );
+        assert_eq!(
+            read_smf(b"RIFF0000WAVEfmt ").err().unwrap(),
+            "not a Standard MIDI File"
+        );
+        let err = import("sysylive_import_empty.mid", &smf(&[&[0x00, 0xff, 0x2f, 0x00]]));
+        assert!(err.unwrap_err().contains("has no notes"));
+    }
+}
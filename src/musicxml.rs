@@ -0,0 +1,328 @@
+// --emit musicxml：把程序里的 Score 字面量写成 MusicXML，每个 Score 一个声部。
+// 调号和拍号用声明里（或 ABC 的 K:、M:）给的，没有给时调号写成 C 调、拍号按小节长度推断；
+// 升降号按谱里原来的 b、# 写法，和调号、本小节前面的音不同时才标出。
+
+use crate::ast::*;
+use crate::gen_music::gcd;
+use crate::sheet::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const TYPES: [(i32, &str); 7] = [
+    (1, "whole"),
+    (2, "half"),
+    (4, "quarter"),
+    (8, "eighth"),
+    (16, "16th"),
+    (32, "32nd"),
+    (64, "64th"),
+];
+
+fn accidental(alter: i32) -> &'static str {
+    match alter {
+        -2 => "flat-flat",
+        -1 => "flat",
+        1 => "sharp",
+        2 => "double-sharp",
+        _ => "natural",
+    }
+}
+
+fn dynamic_mark(velocity: i32) -> &'static str {
+    match velocity {
+        ..=24 => "ppp",
+        25..=40 => "pp",
+        41..=56 => "p",
+        57..=72 => "mp",
+        73..=88 => "mf",
+        89..=104 => "f",
+        105..=119 => "ff",
+        _ => "fff",
+    }
+}
+
+/// 一个声部里所有音的时值分母的最小公倍数，作为每拍的 divisions
+fn divisions(bars: &[SheetBar]) -> i32 {
+    let mut ret = 1;
+    for note in bars.iter().flat_map(|b| b.notes.iter()) {
+        ret = ret / gcd(ret, note.duration.1) * note.duration.1;
+    }
+    ret
+}
+
+/// altered 记下本小节里每个音名和八度上一次的升降号，没有记下的按调号 key_alters；
+/// tuplets 是正在进行的连音层数
+fn write_note(
+    out: &mut String,
+    note: &SheetNote,
+    divisions: i32,
+    key_alters: &[i32; 7],
+    altered: &mut HashMap<(i32, i32), i32>,
+    tuplets: &mut usize,
+) {
+    let duration = note.duration.0 * divisions / note.duration.1;
+    let value = note_value(note.written());
+    let pitches: Vec<Option<&(i32, Spelling)>> = if note.pitches.is_empty() {
+        vec![None]
+    } else {
+        note.pitches.iter().map(Some).collect()
+    };
+    for (k, pitch) in pitches.iter().enumerate() {
+        out.push_str("      <note>\n");
+        if k > 0 {
+            out.push_str("        <chord/>\n");
+        }
+        let mut accidental_mark = None;
+        match pitch {
+            Some((half, spelling)) => {
+                let octave = spelling.octave(*half);
+                out.push_str("        <pitch>\n");
+                let _ = writeln!(
+                    out,
+                    "          <step>{}</step>",
+                    &"CDEFGAB"[spelling.step as usize..][..1]
+                );
+                if spelling.alter != 0 {
+                    let _ = writeln!(out, "          <alter>{}</alter>", spelling.alter);
+                }
+                let _ = writeln!(out, "          <octave>{}</octave>", octave);
+                out.push_str("        </pitch>\n");
+                // 和本小节里同一个位置上一次的升降号（或者调号）不同时才标
+                let last = altered
+                    .insert((spelling.step, octave), spelling.alter)
+                    .unwrap_or(key_alters[spelling.step as usize]);
+                if last != spelling.alter && !note.tie_stop {
+                    accidental_mark = Some(accidental(spelling.alter));
+                }
+            }
+            None => out.push_str("        <rest/>\n"),
+        }
+        let _ = writeln!(out, "        <duration>{}</duration>", duration);
+        if note.tie_stop {
+            out.push_str("        <tie type=\"stop\"/>\n");
+        }
+        if note.tie_start {
+            out.push_str("        <tie type=\"start\"/>\n");
+        }
+        out.push_str("        <voice>1</voice>\n");
+        if let Some((value, dots)) = value {
+            let name = TYPES.iter().find(|t| t.0 == value).unwrap().1;
+            let _ = writeln!(out, "        <type>{}</type>", name);
+            for _ in 0..dots {
+                out.push_str("        <dot/>\n");
+            }
+        }
+        if let Some(mark) = accidental_mark {
+            let _ = writeln!(out, "        <accidental>{}</accidental>", mark);
+        }
+        if note.tuplet != (1, 1) {
+            out.push_str("        <time-modification>\n");
+            let _ = writeln!(
+                out,
+                "          <actual-notes>{}</actual-notes>",
+                note.tuplet.0
+            );
+            let _ = writeln!(
+                out,
+                "          <normal-notes>{}</normal-notes>",
+                note.tuplet.1
+            );
+            out.push_str("        </time-modification>\n");
+        }
+        let mut notations = String::new();
+        if note.tie_stop {
+            notations.push_str("          <tied type=\"stop\"/>\n");
+        }
+        if note.tie_start {
+            notations.push_str("          <tied type=\"start\"/>\n");
+        }
+        // 连音的括号只挂在和弦的第一个音上
+        if k == 0 {
+            for _ in note.tuplet_starts.iter() {
+                *tuplets += 1;
+                let _ = writeln!(
+                    notations,
+                    "          <tuplet type=\"start\" number=\"{}\"/>",
+                    tuplets
+                );
+            }
+            for _ in 0..note.tuplet_stops {
+                let _ = writeln!(
+                    notations,
+                    "          <tuplet type=\"stop\" number=\"{}\"/>",
+                    tuplets
+                );
+                *tuplets -= 1;
+            }
+        }
+        let marks: Vec<&str> = [
+            (STACCATO, "staccato"),
+            (TENUTO, "tenuto"),
+            (ACCENT, "accent"),
+        ]
+        .iter()
+        .filter(|(flag, _)| note.articulation & flag != 0)
+        .map(|(_, name)| *name)
+        .collect();
+        if !marks.is_empty() {
+            notations.push_str("          <articulations>\n");
+            for mark in marks {
+                let _ = writeln!(notations, "            <{}/>", mark);
+            }
+            notations.push_str("          </articulations>\n");
+        }
+        if note.articulation & FERMATA != 0 {
+            notations.push_str("          <fermata/>\n");
+        }
+        if !notations.is_empty() {
+            out.push_str("        <notations>\n");
+            out.push_str(&notations);
+            out.push_str("        </notations>\n");
+        }
+        out.push_str("      </note>\n");
+    }
+}
+
+fn write_direction(out: &mut String, direction: &str) {
+    out.push_str("      <direction placement=\"below\">\n        <direction-type>\n");
+    let _ = writeln!(out, "          {}", direction);
+    out.push_str("        </direction-type>\n      </direction>\n");
+}
+
+fn write_part(out: &mut String, id: usize, bars: &[SheetBar], key: Option<KeySignature>) {
+    let divisions = divisions(bars);
+    let key_alters = key.map_or([0; 7], |key| key.alters());
+    let _ = writeln!(out, "  <part id=\"P{}\">", id);
+    let mut wedge = false;
+    for (i, bar) in bars.iter().enumerate() {
+        let implicit = if bar.pickup { " implicit=\"yes\"" } else { "" };
+        let _ = writeln!(out, "    <measure number=\"{}\"{}>", i + 1, implicit);
+        if i == 0 || bar.time.is_some() {
+            out.push_str("      <attributes>\n");
+            if i == 0 {
+                let _ = writeln!(out, "        <divisions>{}</divisions>", divisions);
+                out.push_str("        <key>\n");
+                let _ = writeln!(
+                    out,
+                    "          <fifths>{}</fifths>",
+                    key.map_or(0, |k| k.fifths)
+                );
+                if let Some(key) = key {
+                    let _ = writeln!(out, "          <mode>{}</mode>", key.mode);
+                }
+                out.push_str("        </key>\n");
+            }
+            if let Some((num, den)) = bar.time {
+                out.push_str("        <time>\n");
+                let _ = writeln!(out, "          <beats>{}</beats>", num);
+                let _ = writeln!(out, "          <beat-type>{}</beat-type>", den);
+                out.push_str("        </time>\n");
+            }
+            if i == 0 {
+                out.push_str("        <clef>\n          <sign>G</sign>\n          <line>2</line>\n        </clef>\n");
+            }
+            out.push_str("      </attributes>\n");
+        }
+        let mut altered = HashMap::new();
+        let mut tuplets = 0;
+        for note in bar.notes.iter() {
+            for dynamic in note.dynamics.iter() {
+                if wedge {
+                    write_direction(out, "<wedge type=\"stop\"/>");
+                }
+                match dynamic {
+                    Dynamic::Level(v) => write_direction(
+                        out,
+                        &format!("<dynamics><{}/></dynamics>", dynamic_mark(*v)),
+                    ),
+                    Dynamic::Cresc => write_direction(out, "<wedge type=\"crescendo\"/>"),
+                    Dynamic::Dim => write_direction(out, "<wedge type=\"diminuendo\"/>"),
+                }
+                wedge = !matches!(dynamic, Dynamic::Level(_));
+            }
+            write_note(
+                out,
+                note,
+                divisions,
+                &key_alters,
+                &mut altered,
+                &mut tuplets,
+            );
+        }
+        out.push_str("    </measure>\n");
+    }
+    out.push_str("  </part>\n");
+}
+
+/// 所有 Score 字面量的 MusicXML；没有 Score 字面量时返回 None
+pub fn musicxml(ast: &CompUnit) -> Option<String> {
+    let scores = score_literals(ast);
+    if scores.is_empty() {
+        return None;
+    }
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+    out.push_str("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n");
+    out.push_str("<score-partwise version=\"4.0\">\n  <part-list>\n");
+    for (i, (name, _)) in scores.iter().enumerate() {
+        let _ = writeln!(out, "    <score-part id=\"P{}\">", i + 1);
+        let _ = writeln!(out, "      <part-name>{}</part-name>", name);
+        out.push_str("    </score-part>\n");
+    }
+    out.push_str("  </part-list>\n");
+    for (i, (_, score)) in scores.iter().enumerate() {
+        write_part(&mut out, i + 1, &sheet_bars(score), score.key);
+    }
+    out.push_str("</score-partwise>\n");
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysy::CompUnitParser;
+    use std::cell::RefCell;
+
+    fn export(decls: &str) -> String {
+        let strings = RefCell::new(Vec::new());
+        let src = format!("int main() {{ {} return 0; }}", decls);
+        let ast = CompUnitParser::new().parse(&strings, &src).unwrap();
+        musicxml(&ast).unwrap()
+    }
+
+    #[test]
+    fn declared_meter() {
+        let out = export("Score s(syllablename = \"1 2 3 | 5 - -\", meter = \"6/8\");");
+        assert!(out.contains("<beats>6</beats>\n          <beat-type>8</beat-type>"));
+        assert_eq!(out.matches("<time>").count(), 1);
+        assert!(!out.contains("<mode>"));
+    }
+
+    #[test]
+    fn key_signature() {
+        let out = export("Score s(abc = \"X:1\\nL:1/4\\nK:Dm\\nB ^c d =B | _B =B |]\");");
+        assert!(out.contains("<fifths>-1</fifths>\n          <mode>minor</mode>"));
+        // 和调号一样的 Bb 不标，本小节改过的才标
+        let marks: Vec<&str> = out
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("<accidental>"))
+            .collect();
+        assert_eq!(
+            marks,
+            [
+                "sharp</accidental>",
+                "natural</accidental>",
+                "natural</accidental>"
+            ]
+        );
+    }
+
+    #[test]
+    fn pickup_measure() {
+        let out = export("Score s(syllablename = \"5 | 1 2 3 | 5 - - | 4 3\");");
+        assert!(out.contains("<measure number=\"1\" implicit=\"yes\">"));
+        assert!(out.contains("<measure number=\"2\">"));
+        assert_eq!(out.matches("<time>").count(), 1);
+        assert!(out.contains("<beats>3</beats>"));
+    }
+}
@@ -0,0 +1,1030 @@
+// 音乐对象声明的具名参数，例如 Score x(key = "Eb", syllablename = "...")。
+// 这些参数都在编译期处理：调号把简谱的唱名换算成绝对音高，字母音名本身就是绝对音高。
+
+use crate::abc_import::import_abc;
+use crate::ast::*;
+use crate::diagnostic::*;
+use crate::gen_music::{bar_beats, gcd};
+use crate::midi_import::import_midi;
+use crate::sysy::{BarParser, DurationParser, NoteParser, ScoreParser};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+/// 声明括号里的一个 name = "value" 或者 name = 整数
+pub struct MusicArg<'a> {
+    pub name: String,
+    pub span: Span,
+    pub value: Cow<'a, str>,
+    pub offset: usize, // value 在源码中的起始位置，用来平移子解析器的报错位置
+    pub value_span: Span,
+}
+
+const MUSIC_OPTIONS: [&str; 7] = [
+    "syllablename",
+    "key",
+    "meter",
+    "midi",
+    "track",
+    "abc",
+    "duration",
+];
+
+struct MusicOptions<'a> {
+    syllablename: Option<MusicArg<'a>>,
+    key: Option<Spelling>, // 主音，也就是 1 的音名
+    meter: Option<Meter>,
+    midi: Option<MusicArg<'a>>,
+    track: Option<usize>,
+    abc: Option<MusicArg<'a>>,
+    duration: Option<MusicArg<'a>>,
+}
+
+impl<'a> MusicOptions<'a> {
+    fn new(args: Vec<MusicArg<'a>>, span: Span) -> Result<MusicOptions<'a>, Diagnostic> {
+        let mut syllablename = None;
+        let mut key = None;
+        let mut meter = None;
+        let mut midi = None;
+        let mut track = None;
+        let mut abc = None;
+        let mut duration = None;
+        let mut seen: Vec<String> = Vec::new();
+        for arg in args.into_iter() {
+            if !MUSIC_OPTIONS.contains(&arg.name.as_str()) {
+                return Err(Diagnostic::error(
+                    arg.span,
+                    format!(
+                        "unknown option `{}`, expected one of `{}`",
+                        arg.name,
+                        MUSIC_OPTIONS.join("`, `")
+                    ),
+                ));
+            }
+            if seen.contains(&arg.name) {
+                return Err(Diagnostic::error(
+                    arg.span,
+                    format!("option `{}` is specified more than once", arg.name),
+                ));
+            }
+            seen.push(arg.name.clone());
+            match arg.name.as_str() {
+                "key" => key = Some(key_offset(&arg.value, arg.value_span)?),
+                "meter" => meter = Some(parse_meter(&arg)?),
+                "midi" => midi = Some(arg),
+                "abc" => abc = Some(arg),
+                "duration" => duration = Some(arg),
+                "track" => match arg.value.parse::<usize>() {
+                    Ok(n) => track = Some(n),
+                    Err(_) => {
+                        return Err(Diagnostic::error(
+                            arg.value_span,
+                            format!("invalid track `{}`, expected a track number", arg.value),
+                        ))
+                    }
+                },
+                _ => syllablename = Some(arg),
+            }
+        }
+        let sources: Vec<&MusicArg> = [&syllablename, &midi, &abc].into_iter().flatten().collect();
+        if sources.len() > 1 {
+            return Err(Diagnostic::error(
+                sources[0].span,
+                format!(
+                    "options `{}` and `{}` cannot be used together",
+                    sources[0].name, sources[1].name
+                ),
+            ));
+        }
+        if let (Some(arg), true) = (&abc, seen.iter().any(|name| name == "key")) {
+            return Err(Diagnostic::error(
+                arg.span,
+                "option `key` cannot be used with `abc`, the key is given by the `K:` field",
+            ));
+        }
+        if let (Some(arg), true) = (&midi, seen.iter().any(|name| name == "key")) {
+            return Err(Diagnostic::error(
+                arg.span,
+                "option `key` cannot be used with `midi`, MIDI notes already have absolute pitches",
+            ));
+        }
+        if track.is_some() && midi.is_none() {
+            return Err(Diagnostic::error(span, "option `track` requires option `midi`"));
+        }
+        Ok(MusicOptions {
+            syllablename,
+            key,
+            meter,
+            midi,
+            track,
+            abc,
+            duration,
+        })
+    }
+
+    fn syllablename(&self, span: Span) -> Result<&MusicArg<'a>, Diagnostic> {
+        match (&self.syllablename, self.midi.as_ref().or(self.abc.as_ref())) {
+            (Some(arg), _) => Ok(arg),
+            (None, Some(arg)) => Err(Diagnostic::error(
+                arg.span,
+                format!("option `{}` is only allowed for Score", arg.name),
+            )),
+            (None, None) => Err(Diagnostic::error(span, "missing option `syllablename`")),
+        }
+    }
+
+    fn no_duration(&self) -> Result<(), Diagnostic> {
+        match &self.duration {
+            Some(arg) => Err(Diagnostic::error(
+                arg.span,
+                "option `duration` is only allowed for Note",
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Score、Bar、Note 带具名参数的声明；Track 和 Instrument 没有具名参数
+pub fn music_decl(
+    strings: &RefCell<Vec<String>>,
+    ty: MusicType,
+    id: String,
+    args: Vec<MusicArg>,
+    span: Span,
+) -> Result<VarDef, Diagnostic> {
+    match ty {
+        MusicType::Score => Ok(VarDef::ScoreInit(id, Box::new(score_decl(strings, args, span)?))),
+        MusicType::Bar => Ok(VarDef::BarInit(id, Box::new(bar_decl(strings, args, span)?))),
+        MusicType::Note => {
+            let (note, fz, fm) = note_decl(strings, args, span)?;
+            Ok(VarDef::NoteInit(id, Box::new(note), fz, fm))
+        }
+        MusicType::Track | MusicType::Instrument => Err(Diagnostic::error(
+            args[0].span,
+            format!("`{:?}` cannot be declared with named options", ty),
+        )),
+    }
+}
+
+pub fn score_decl(
+    strings: &RefCell<Vec<String>>,
+    args: Vec<MusicArg>,
+    span: Span,
+) -> Result<Score, Diagnostic> {
+    let opts = MusicOptions::new(args, span)?;
+    opts.no_duration()?;
+    if let Some(midi) = &opts.midi {
+        let meter = opts.meter.as_ref().map(|m| m.written);
+        return import_midi(&midi.value, opts.track, meter, midi.value_span)
+            .map_err(|e| Diagnostic::error(midi.value_span, e));
+    }
+    // ABC 曲子和简谱一样，位置相对于字符串开头，展开反复、换算力度的过程也一样
+    let mut meter = opts.meter.as_ref().map(|m| m.written);
+    let mut key = opts.key.map(|tonic| KeySignature::new(tonic, "major"));
+    let (mut bars, bpm, offset) = match &opts.abc {
+        Some(abc) => {
+            let tune = import_abc(&abc.value).map_err(|e| shift_error(e, abc.offset))?;
+            meter = meter.or(tune.meter);
+            key = tune.key;
+            (tune.bars, tune.bpm, abc.offset)
+        }
+        None => {
+            let syllablename = opts.syllablename(span)?;
+            let offset = syllablename.offset;
+            let bars = ScoreParser::new()
+                .parse(strings, &syllablename.value)
+                .map_err(|e| from_parse_error(e, offset))?;
+            (bars, None, offset)
+        }
+    };
+    // 拍号按写出来的小节检查，展开反复之前做
+    for (i, bar) in bars.iter_mut().enumerate() {
+        bar.bar.span = shift(bar.bar.span, offset);
+        for (_, span) in bar.marks.iter_mut() {
+            *span = shift(*span, offset);
+        }
+        check_meter(&bar.bar, i + 1, &opts.meter);
+    }
+    let mut score = Score {
+        bars: expand_repeats(bars)?,
+        bpm,
+        meter,
+        key,
+    };
+    resolve_dynamics(&mut score.bars).map_err(|e| shift_error(e, offset))?;
+    score.transpose_degrees(opts.key.unwrap_or(Spelling::C));
+    Ok(score)
+}
+
+pub fn note_decl(
+    strings: &RefCell<Vec<String>>,
+    args: Vec<MusicArg>,
+    span: Span,
+) -> Result<(Note, i32, i32), Diagnostic> {
+    let other = args.iter().find(|arg| {
+        MUSIC_OPTIONS.contains(&arg.name.as_str())
+            && arg.name != "syllablename"
+            && arg.name != "duration"
+    });
+    if let Some(arg) = other {
+        return Err(Diagnostic::error(
+            arg.span,
+            format!("option `{}` is not allowed for Note", arg.name),
+        ));
+    }
+    let opts = MusicOptions::new(args, span)?;
+    let syllablename = opts.syllablename(span)?;
+    let duration = opts
+        .duration
+        .as_ref()
+        .ok_or_else(|| Diagnostic::error(span, "missing option `duration`"))?;
+    let note = NoteParser::new()
+        .parse(strings, &syllablename.value)
+        .map_err(|e| from_parse_error(e, syllablename.offset))?;
+    let (fz, fm) = DurationParser::new()
+        .parse(strings, &duration.value)
+        .map_err(|e| from_parse_error(e, duration.offset))?;
+    Ok((note, fz, fm))
+}
+
+pub fn bar_decl(
+    strings: &RefCell<Vec<String>>,
+    args: Vec<MusicArg>,
+    span: Span,
+) -> Result<Bar, Diagnostic> {
+    let opts = MusicOptions::new(args, span)?;
+    opts.no_duration()?;
+    let syllablename = opts.syllablename(span)?;
+    let mut bar = BarParser::new()
+        .parse(strings, &syllablename.value)
+        .map_err(|e| from_parse_error(e, syllablename.offset))?;
+    resolve_dynamics(std::slice::from_mut(&mut bar))
+        .map_err(|e| shift_error(e, syllablename.offset))?;
+    bar.transpose_degrees(opts.key.unwrap_or(Spelling::C));
+    bar.span = shift(bar.span, syllablename.offset);
+    check_meter(&bar, 1, &opts.meter);
+    Ok(bar)
+}
+
+fn shift(span: Span, offset: usize) -> Span {
+    Span::new(span.lo + offset, span.hi + offset)
+}
+
+fn shift_error(mut error: Diagnostic, offset: usize) -> Diagnostic {
+    error.span = shift(error.span, offset);
+    error
+}
+
+struct Meter {
+    beats: (i32, i32),   // 每小节的拍数，以四分音符为一拍，化成最简分数
+    written: (i32, i32), // 原样的分子分母
+    text: String,
+}
+
+/// 拍号，例如 "3/4"；以四分音符为一拍，6/8 就是每小节 3 拍
+fn parse_meter(arg: &MusicArg) -> Result<Meter, Diagnostic> {
+    let (fz, fm) = DurationParser::new()
+        .parse(&RefCell::new(Vec::new()), &arg.value)
+        .map_err(|e| from_parse_error(e, arg.offset))?;
+    if fz <= 0 || fm <= 0 {
+        return Err(Diagnostic::error(
+            arg.value_span,
+            format!(
+                "invalid meter `{}`, expected something like `4/4` or `6/8`",
+                arg.value
+            ),
+        ));
+    }
+    let g = gcd(fz * 4, fm);
+    Ok(Meter {
+        beats: (fz * 4 / g, fm / g),
+        written: (fz, fm),
+        text: arg.value.to_string(),
+    })
+}
+
+fn fmt_beats(fz: i32, fm: i32) -> String {
+    if fm == 1 {
+        format!("{}", fz)
+    } else {
+        format!("{}/{}", fz, fm)
+    }
+}
+
+/// 小节的拍数必须和拍号一致；延续音（-、_）占本小节的拍数，所以跨小节线的连音不受影响。
+/// 出错时只记下错误，不中断解析，这样所有不合拍的小节都会报出来
+fn check_meter(bar: &Bar, index: usize, meter: &Option<Meter>) {
+    if let Some(Meter {
+        beats: (mz, mm),
+        text,
+        ..
+    }) = meter
+    {
+        let (fz, fm) = bar_beats(bar);
+        if (fz, fm) != (*mz, *mm) {
+            let what = if fz * mm > mz * fm {
+                "too long"
+            } else {
+                "too short"
+            };
+            report(Diagnostic::error(
+                bar.span,
+                format!(
+                    "bar {} is {}: it has {} beat(s), but meter `{}` requires {}",
+                    index,
+                    what,
+                    fmt_beats(fz, fm),
+                    text,
+                    fmt_beats(*mz, *mm)
+                ),
+            ));
+        }
+    }
+}
+
+/// 音名（C D E F G A B，可带 # 或 b）相对 C 的半音数
+fn letter_spelling(s: &str) -> Option<(Spelling, &str)> {
+    let mut chars = s.chars();
+    let step = "CDEFGAB".find(chars.next()?)? as i32;
+    let rest = chars.as_str();
+    if let Some(rest) = rest.strip_prefix('#') {
+        Some((Spelling { step, alter: 1 }, rest))
+    } else if let Some(rest) = rest.strip_prefix('b') {
+        Some((Spelling { step, alter: -1 }, rest))
+    } else {
+        Some((Spelling { step, alter: 0 }, rest))
+    }
+}
+
+/// 调号，例如 "Eb"：1 唱作 Eb4，即升 3 个半音
+pub fn key_offset(s: &str, span: Span) -> Result<Spelling, Diagnostic> {
+    match letter_spelling(s) {
+        Some((key, "")) => Ok(key),
+        _ => Err(Diagnostic::error(
+            span,
+            format!(
+                "invalid key `{}`, expected a note name such as `C`, `F#` or `Eb`",
+                s
+            ),
+        )),
+    }
+}
+
+/// 谱里的一个单词：力度记号，或者字母音名
+pub fn note_word(s: &str, span: Span) -> Result<NoteElem, Diagnostic> {
+    let dynamic = match s {
+        "ppp" => Dynamic::Level(16),
+        "pp" => Dynamic::Level(33),
+        "p" => Dynamic::Level(49),
+        "mp" => Dynamic::Level(64),
+        "mf" => Dynamic::Level(80),
+        "f" => Dynamic::Level(96),
+        "ff" => Dynamic::Level(112),
+        "fff" => Dynamic::Level(127),
+        "cresc" => Dynamic::Cresc,
+        "dim" => Dynamic::Dim,
+        _ => return letter_note(s, span).map(NoteElem::Note),
+    };
+    Ok(NoteElem::Dynamic(dynamic, span))
+}
+
+/// 字母音名，例如 C4、D#4、Bb3；C4 就是 C 调的 1
+pub fn letter_note(s: &str, span: Span) -> Result<Note, Diagnostic> {
+    if let Some((spelling, octave)) = letter_spelling(s) {
+        if octave.len() == 1 {
+            if let Some(octave) = octave.chars().next().unwrap().to_digit(10) {
+                return Ok(Note::Pitch(spelling.semitone() + (octave as i32 - 4) * 12, spelling));
+            }
+        }
+    }
+    Err(Diagnostic::error(
+        span,
+        format!(
+            "invalid note name `{}`, expected a letter name such as `C4`, `D#4` or `Bb3`",
+            s
+        ),
+    ))
+}
+
+// 反复记号。谱里可以写：
+//   |: 和 :| 反复，:|: 同时结束一段反复并开始下一段；
+//   (1) (2) 房子（volta），从标记的小节开始，到 :| 为止；
+//   Segno、Fine、Coda 记号，以及 D.C.、D.S.（可以带 al Fine 或 al Coda）跳转。
+// 编译期把它们按演奏顺序展开成一串小节，gen_score 照常生成。
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BarMark {
+    Segno,
+    Fine,
+    Coda,
+    Jump(bool, Option<String>), // 是否 D.S.（否则 D.C.），以及 al 后面的 Fine/Coda
+    Volta(i32),
+}
+
+impl BarMark {
+    pub fn jump(s: &str) -> BarMark {
+        let mut words = s.split_whitespace();
+        let dal_segno = words.next() == Some("D.S.");
+        BarMark::Jump(dal_segno, words.nth(1).map(|w| w.to_string()))
+    }
+}
+
+/// 谱里写出来的一个小节，以及它前后的反复记号
+#[derive(Debug, Clone)]
+pub struct ScoreBar {
+    pub bar: Bar,
+    pub marks: Vec<(BarMark, Span)>,
+    pub repeat_start: bool,
+    pub repeat_end: bool,
+    pub degree_at: Option<usize>, // 小节以简谱唱名开头时它的位置，用来发现紧跟在 |: 后面的音
+}
+
+impl ScoreBar {
+    pub fn new(bar: Bar, marks: Vec<(BarMark, Span)>) -> ScoreBar {
+        ScoreBar {
+            bar,
+            marks,
+            repeat_start: false,
+            repeat_end: false,
+            degree_at: None,
+        }
+    }
+
+    fn has(&self, mark: &BarMark) -> bool {
+        self.marks.iter().any(|(m, _)| m == mark)
+    }
+
+    fn volta(&self) -> Option<i32> {
+        self.marks.iter().find_map(|(m, _)| match m {
+            BarMark::Volta(n) => Some(*n),
+            _ => None,
+        })
+    }
+
+    fn jump(&self) -> Option<(bool, &Option<String>, Span)> {
+        self.marks.iter().find_map(|(m, span)| match m {
+            BarMark::Jump(dal_segno, al) => Some((*dal_segno, al, *span)),
+            _ => None,
+        })
+    }
+}
+
+/// 谱里小节线之间的一项：音符，或者反复记号
+pub enum ScoreItem {
+    Note(NoteElem, Span),
+    Mark(BarMark, Span),
+}
+
+/// 音符后面的后缀：(附点个数, /n 和 *n, 演奏法)
+pub type Suffix = (usize, Vec<(i32, i32)>, i32);
+
+/// 给 elem 加上时值后缀和演奏法后缀，span 是整个元素（含后缀）的位置
+pub fn with_suffix(elem: NoteElem, suffix: Suffix, span: Span) -> Result<NoteElem, Diagnostic> {
+    let (dots, scales, flags) = suffix;
+    if let NoteElem::Dynamic(..) = elem {
+        if dots > 0 || !scales.is_empty() || flags != 0 {
+            return Err(Diagnostic::error(
+                span,
+                "a dynamic marking cannot take duration or articulation suffixes",
+            ));
+        }
+    }
+    let mut ret = elem;
+    if dots > 0 {
+        let fm = 1 << dots;
+        ret = NoteElem::Scaled(Box::new(ret), fm * 2 - 1, fm);
+    }
+    for (fz, fm) in scales.into_iter() {
+        ret = NoteElem::Scaled(Box::new(ret), fz, fm);
+    }
+    if flags != 0 {
+        ret = NoteElem::Articulated(Box::new(ret), flags);
+    }
+    Ok(ret)
+}
+
+/// Score 里的一个单词：Segno、Fine、Coda 是反复记号，其余的按 note_word 处理。
+/// 这几个词只在谱里有意义，不是关键字，SysY 代码里照样可以用作变量名
+pub fn score_word(s: &str, word: Span, suffix: Suffix, span: Span) -> Result<ScoreItem, Diagnostic> {
+    let mark = match s {
+        "Segno" => BarMark::Segno,
+        "Fine" => BarMark::Fine,
+        "Coda" => BarMark::Coda,
+        _ => {
+            let elem = note_word(s, word)?;
+            return Ok(ScoreItem::Note(with_suffix(elem, suffix, span)?, span));
+        }
+    };
+    if suffix != (0, Vec::new(), 0) {
+        return Err(Diagnostic::error(
+            span,
+            format!("`{}` cannot take duration or articulation suffixes", s),
+        ));
+    }
+    Ok(ScoreItem::Mark(mark, word))
+}
+
+/// 是不是以简谱唱名开头（可能带着后缀）
+fn is_degree(elem: &NoteElem) -> bool {
+    match elem {
+        NoteElem::Note(Note::Semitone(..)) => true,
+        NoteElem::Scaled(elem, _, _) | NoteElem::Articulated(elem, _) => is_degree(elem),
+        _ => false,
+    }
+}
+
+/// 两条小节线之间的内容：反复记号只能写在音符的前面或后面
+pub fn score_bar(items: Vec<ScoreItem>) -> Result<ScoreBar, Diagnostic> {
+    let degree_at = match items.first() {
+        Some(ScoreItem::Note(elem, span)) if is_degree(elem) => Some(span.lo),
+        _ => None,
+    };
+    let mut notes = Vec::new();
+    let mut marks: Vec<(BarMark, Span)> = Vec::new();
+    let mut span: Option<Span> = None;
+    let mut trailing: Option<Span> = None; // 写在音符后面的第一个记号
+    for item in items.into_iter() {
+        match item {
+            ScoreItem::Note(elem, note_span) => {
+                if let Some(mark_span) = trailing {
+                    return Err(Diagnostic::error(
+                        mark_span,
+                        "repeat marks must be written before or after the notes of a bar",
+                    ));
+                }
+                notes.push(elem);
+                let lo = span.map_or(note_span.lo, |s| s.lo);
+                span = Some(Span::new(lo, note_span.hi));
+            }
+            ScoreItem::Mark(mark, mark_span) => {
+                if span.is_some() && trailing.is_none() {
+                    trailing = Some(mark_span);
+                }
+                marks.push((mark, mark_span));
+            }
+        }
+    }
+    let Some(span) = span else {
+        let (_, mark_span) = marks[0];
+        return Err(Diagnostic::error(mark_span, "a bar must contain at least one note"));
+    };
+    let mut bar = ScoreBar::new(Bar { notes, span }, marks);
+    bar.degree_at = degree_at;
+    Ok(bar)
+}
+
+/// |: 后面紧跟着简谱唱名时，原来的写法 |:5 是小节线加升八度的 :5，现在会被读成反复记号加 5，
+/// 两种意思都说得通，所以直接报错，让用户用空格分开
+fn check_repeat_start(barline: Span, bar: &ScoreBar) -> Result<(), Diagnostic> {
+    if bar.degree_at == Some(barline.hi) {
+        return Err(Diagnostic::error(
+            Span::new(barline.lo, barline.hi + 1),
+            "ambiguous `|:` before a note: write `|: 5` for a repeat sign or `| :5` for a note an octave up",
+        ));
+    }
+    Ok(())
+}
+
+/// 按小节线把小节连起来；小节线是 (开始反复, 结束反复, 位置)
+pub fn score_bars(
+    start: Option<Span>,
+    first: ScoreBar,
+    rest: Vec<((bool, bool, Span), ScoreBar)>,
+    end: Option<(bool, bool, Span)>,
+) -> Result<Vec<ScoreBar>, Diagnostic> {
+    if let Some(span) = start {
+        check_repeat_start(span, &first)?;
+    }
+    let mut bars = vec![first];
+    bars[0].repeat_start = start.is_some();
+    for ((repeat_start, repeat_end, span), mut bar) in rest.into_iter() {
+        if repeat_start {
+            check_repeat_start(span, &bar)?;
+        }
+        bars.last_mut().unwrap().repeat_end = repeat_end;
+        bar.repeat_start = repeat_start;
+        bars.push(bar);
+    }
+    if let Some((repeat_start, repeat_end, span)) = end {
+        if repeat_start {
+            return Err(Diagnostic::error(
+                span,
+                "repeat starts at the end of the score",
+            ));
+        }
+        bars.last_mut().unwrap().repeat_end = repeat_end;
+    }
+    Ok(bars)
+}
+
+fn find_marks(bars: &[ScoreBar], mark: &BarMark) -> Vec<usize> {
+    (0..bars.len()).filter(|&i| bars[i].has(mark)).collect()
+}
+
+/// 每个小节所在的房子：(编号, 同一组房子里最大的编号)
+fn volta_regions(bars: &[ScoreBar]) -> Result<Vec<Option<(i32, i32)>>, Diagnostic> {
+    let n = bars.len();
+    let mut regions = vec![None; n];
+    let mut i = 0;
+    while i < n {
+        if bars[i].volta().is_none() {
+            i += 1;
+            continue;
+        }
+        let group = i;
+        let mut expected = 1;
+        loop {
+            let num = bars[i].volta().unwrap();
+            if num != expected {
+                let span = bars[i]
+                    .marks
+                    .iter()
+                    .find(|(m, _)| m == &BarMark::Volta(num))
+                    .unwrap()
+                    .1;
+                return Err(Diagnostic::error(
+                    span,
+                    format!("expected ending ({}), found ({})", expected, num),
+                ));
+            }
+            // 房子到 :| 为止，遇到下一个房子或者 |: 也结束
+            let mut end = i;
+            while !bars[end].repeat_end
+                && end + 1 < n
+                && bars[end + 1].volta().is_none()
+                && !bars[end + 1].repeat_start
+            {
+                end += 1;
+            }
+            for region in regions[i..=end].iter_mut() {
+                *region = Some((num, 0));
+            }
+            i = end + 1;
+            if i >= n || bars[i].volta().is_none() {
+                break;
+            }
+            if !bars[end].repeat_end {
+                return Err(Diagnostic::error(
+                    bars[end].bar.span,
+                    format!("ending ({}) must end with `:|`", num),
+                ));
+            }
+            expected += 1;
+        }
+        for region in regions[group..i].iter_mut() {
+            *region = region.map(|(num, _)| (num, expected));
+        }
+    }
+    Ok(regions)
+}
+
+/// 按演奏顺序展开。D.C./D.S. 之后不再反复，房子只走最后一个
+pub fn expand_repeats(bars: Vec<ScoreBar>) -> Result<Vec<Bar>, Diagnostic> {
+    let regions = volta_regions(&bars)?;
+    let segnos = find_marks(&bars, &BarMark::Segno);
+    let fines = find_marks(&bars, &BarMark::Fine);
+    let codas = find_marks(&bars, &BarMark::Coda);
+    let jumps: Vec<usize> = (0..bars.len())
+        .filter(|&i| bars[i].jump().is_some())
+        .collect();
+    if jumps.len() > 1 {
+        let (_, _, span) = bars[jumps[1]].jump().unwrap();
+        return Err(Diagnostic::error(span, "only one D.C. or D.S. is allowed"));
+    }
+    if segnos.len() > 1 {
+        return Err(Diagnostic::error(
+            bars[segnos[1]].bar.span,
+            "more than one `Segno`",
+        ));
+    }
+    if fines.len() > 1 {
+        return Err(Diagnostic::error(
+            bars[fines[1]].bar.span,
+            "more than one `Fine`",
+        ));
+    }
+    if codas.len() > 2 {
+        return Err(Diagnostic::error(
+            bars[codas[2]].bar.span,
+            "more than two `Coda` marks",
+        ));
+    }
+    if let Some(&i) = jumps.first() {
+        let (dal_segno, al, span) = bars[i].jump().unwrap();
+        if dal_segno && segnos.is_empty() {
+            return Err(Diagnostic::error(span, "`D.S.` without a `Segno`"));
+        }
+        match al.as_deref() {
+            Some("Fine") if fines.is_empty() => {
+                return Err(Diagnostic::error(span, "`al Fine` without a `Fine`"));
+            }
+            // 第一个 Coda 是跳转处，第二个是尾声开始的小节
+            Some("Coda") if codas.len() != 2 => {
+                return Err(Diagnostic::error(span, "`al Coda` needs two `Coda` marks"));
+            }
+            _ => {}
+        }
+    }
+
+    let mut ret = Vec::new();
+    let (mut pos, mut pass, mut repeat_start) = (0, 1, 0);
+    let mut jumped = false;
+    let mut back = false; // 刚从 :| 跳回来，不要把 pass 清零
+    while pos < bars.len() {
+        let bar = &bars[pos];
+        if bar.repeat_start && !back {
+            repeat_start = pos;
+            pass = 1;
+        }
+        back = false;
+        let play = match regions[pos] {
+            None => true,
+            Some((num, last)) if jumped => num == last,
+            Some((num, _)) => num == pass,
+        };
+        if play {
+            ret.push(bar.bar.clone());
+            if jumped && bar.has(&BarMark::Fine) {
+                break;
+            }
+            if jumped && codas.len() == 2 && pos == codas[0] {
+                pos = codas[1];
+                continue;
+            }
+            if bar.repeat_end && !jumped {
+                // 最后一个房子结尾的 :| 不再反复
+                let again = match regions[pos] {
+                    Some((num, last)) if num < last => Some(num + 1),
+                    Some(_) => None,
+                    None if pass == 1 => Some(2),
+                    None => None,
+                };
+                if let Some(next) = again {
+                    pass = next;
+                    pos = repeat_start;
+                    back = true;
+                    continue;
+                }
+                pass = 1;
+                repeat_start = pos + 1;
+            }
+            if let Some((dal_segno, _, _)) = bar.jump() {
+                if !jumped {
+                    jumped = true;
+                    pos = if dal_segno { segnos[0] } else { 0 };
+                    continue;
+                }
+            }
+        }
+        // 走出一组房子之后，后面的 :| 从这里重新开始反复
+        if regions[pos].is_some() && regions.get(pos + 1).is_none_or(|r| r.is_none()) {
+            pass = 1;
+            repeat_start = pos + 1;
+        }
+        pos += 1;
+    }
+    Ok(ret)
+}
+
+// 力度。力度记号对后面所有的音都有效（跨小节），没有记号时是 mf；
+// cresc、dim 从记号处开始，到下一个力度记号为止逐个音均匀变化。
+
+enum DynamicEvent {
+    Note,
+    Mark(Dynamic, Span),
+}
+
+fn dynamic_events(elem: &NoteElem, events: &mut Vec<DynamicEvent>) {
+    match elem {
+        NoteElem::Tie | NoteElem::Note(Note::Rest) => {}
+        NoteElem::Note(_) | NoteElem::Chord(_) | NoteElem::Velocity(..) => {
+            events.push(DynamicEvent::Note)
+        }
+        NoteElem::Notes(elems) => elems.iter().for_each(|e| dynamic_events(e, events)),
+        NoteElem::Scaled(elem, _, _) | NoteElem::Articulated(elem, _) => {
+            dynamic_events(elem, events)
+        }
+        NoteElem::Dynamic(dynamic, span) => events.push(DynamicEvent::Mark(*dynamic, *span)),
+    }
+}
+
+fn apply_velocity(elem: &mut NoteElem, velocities: &mut impl Iterator<Item = i32>) {
+    match elem {
+        NoteElem::Tie | NoteElem::Note(Note::Rest) | NoteElem::Dynamic(..) => {}
+        NoteElem::Note(_) | NoteElem::Chord(_) => {
+            let note = std::mem::replace(elem, NoteElem::Tie);
+            *elem = NoteElem::Velocity(Box::new(note), velocities.next().unwrap());
+        }
+        NoteElem::Velocity(_, velocity) => *velocity = velocities.next().unwrap(),
+        NoteElem::Notes(elems) => elems.iter_mut().for_each(|e| apply_velocity(e, velocities)),
+        NoteElem::Scaled(elem, _, _) | NoteElem::Articulated(elem, _) => {
+            apply_velocity(elem, velocities)
+        }
+    }
+}
+
+/// 按演奏顺序把 bars 里的力度记号换算成每个音的力度
+fn resolve_dynamics(bars: &mut [Bar]) -> Result<(), Diagnostic> {
+    let mut events = Vec::new();
+    for bar in bars.iter() {
+        bar.notes
+            .iter()
+            .for_each(|e| dynamic_events(e, &mut events));
+    }
+    if !events.iter().any(|e| matches!(e, DynamicEvent::Mark(..))) {
+        return Ok(());
+    }
+    let mut velocity = DEFAULT_VELOCITY;
+    let mut hairpin: Option<(usize, Dynamic, Span)> = None; // 从第几个音开始
+    let mut velocities = Vec::new();
+    for event in events.into_iter() {
+        match event {
+            DynamicEvent::Note => velocities.push(velocity),
+            DynamicEvent::Mark(Dynamic::Level(target), span) => {
+                if let Some((start, kind, _)) = hairpin.take() {
+                    if kind == Dynamic::Cresc && target <= velocity {
+                        return Err(Diagnostic::error(
+                            span,
+                            "`cresc` must end at a louder dynamic",
+                        ));
+                    }
+                    if kind == Dynamic::Dim && target >= velocity {
+                        return Err(Diagnostic::error(
+                            span,
+                            "`dim` must end at a softer dynamic",
+                        ));
+                    }
+                    let len = (velocities.len() - start) as i32;
+                    for (k, v) in velocities[start..].iter_mut().enumerate() {
+                        *v = velocity + (target - velocity) * k as i32 / len;
+                    }
+                }
+                velocity = target;
+            }
+            DynamicEvent::Mark(kind, span) => {
+                if hairpin.is_some() {
+                    return Err(Diagnostic::error(
+                        span,
+                        "the previous `cresc` or `dim` has not ended at a dynamic marking",
+                    ));
+                }
+                hairpin = Some((velocities.len(), kind, span));
+            }
+        }
+    }
+    if let Some((_, _, span)) = hairpin {
+        return Err(Diagnostic::error(
+            span,
+            "`cresc` and `dim` must end at a dynamic marking such as `f` or `p`",
+        ));
+    }
+    let mut velocities = velocities.into_iter();
+    for bar in bars.iter_mut() {
+        bar.notes
+            .iter_mut()
+            .for_each(|e| apply_velocity(e, &mut velocities));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysy::CompUnitParser;
+
+    fn parse(text: &str) -> Result<Vec<ScoreBar>, Diagnostic> {
+        let strings = RefCell::new(Vec::new());
+        ScoreParser::new()
+            .parse(&strings, text)
+            .map_err(|e| from_parse_error(e, 0))
+    }
+
+    fn expand(text: &str) -> Result<Vec<Bar>, Diagnostic> {
+        expand_repeats(parse(text)?)
+    }
+
+    fn first_semitone(bar: &Bar) -> Option<i32> {
+        match &bar.notes[0] {
+            NoteElem::Note(note) => note.semitone(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn barline_then_octave_up() {
+        let bars = expand("1 | :5 |").unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(first_semitone(&bars[1]), Some(19));
+    }
+
+    #[test]
+    fn repeat_start_then_note() {
+        let bars = expand("1 |: 5 :|").unwrap();
+        let notes: Vec<_> = bars.iter().map(first_semitone).collect();
+        assert_eq!(notes, [Some(0), Some(7), Some(7)]);
+    }
+
+    #[test]
+    fn ambiguous_repeat_start() {
+        let err = parse("1 |:5 |").unwrap_err();
+        assert!(err.message.starts_with("ambiguous `|:`"));
+        assert_eq!(err.span, Span::new(2, 5));
+        assert!(parse("|:5 |").is_err());
+    }
+
+    #[test]
+    fn repeat_end_without_start() {
+        let bars = expand("1 | 2 :| 3").unwrap();
+        let notes: Vec<_> = bars.iter().map(first_semitone).collect();
+        assert_eq!(notes, [Some(0), Some(2), Some(0), Some(2), Some(4)]);
+    }
+
+    #[test]
+    fn volta_and_fine() {
+        let bars = expand("|: 1 | (1) 2 :| (2) 3 | Fine 4 D.C. al Fine").unwrap();
+        let notes: Vec<_> = bars.iter().map(first_semitone).collect();
+        let expect = [0, 2, 0, 4, 5, 0, 4, 5].map(Some);
+        assert_eq!(notes, expect);
+    }
+
+    #[test]
+    fn last_ending_with_repeat_end() {
+        for text in ["|: 1 | (1) 2 :| (2) 3 :|", "|: 1 | (1) 2 :| (2) 3 |"] {
+            let bars = expand(text).unwrap();
+            let notes: Vec<_> = bars.iter().map(first_semitone).collect();
+            assert_eq!(notes, [0, 2, 0, 4].map(Some));
+        }
+        // 最后一个房子之后的 :| 从房子后面重新开始反复
+        let bars = expand("|: 1 | (1) 2 :| (2) 3 :| 4 :|").unwrap();
+        let notes: Vec<_> = bars.iter().map(first_semitone).collect();
+        assert_eq!(notes, [0, 2, 0, 4, 5, 5].map(Some));
+    }
+
+    #[test]
+    fn mark_between_notes() {
+        let err = parse("1 Fine 2 |").unwrap_err();
+        assert_eq!(err.span, Span::new(2, 6));
+        assert!(parse("Fine. 1 |").is_err());
+        assert!(parse("Segno |").is_err());
+    }
+
+    fn velocities(text: &str) -> Result<Vec<i32>, Diagnostic> {
+        fn collect(elem: &NoteElem, ret: &mut Vec<i32>) {
+            match elem {
+                NoteElem::Velocity(_, velocity) => ret.push(*velocity),
+                NoteElem::Notes(elems) => elems.iter().for_each(|e| collect(e, ret)),
+                NoteElem::Scaled(elem, _, _) | NoteElem::Articulated(elem, _) => {
+                    collect(elem, ret)
+                }
+                _ => {}
+            }
+        }
+        let mut bars = expand(text)?;
+        resolve_dynamics(&mut bars)?;
+        let mut ret = Vec::new();
+        for bar in bars.iter() {
+            bar.notes.iter().for_each(|e| collect(e, &mut ret));
+        }
+        Ok(ret)
+    }
+
+    #[test]
+    fn dynamics() {
+        assert_eq!(velocities("1 2 | 3").unwrap(), []);
+        assert_eq!(velocities("1 p 2 {3 0 4} | - 5").unwrap(), [80, 49, 49, 49, 49]);
+        // 反复展开以后按演奏顺序，第二遍开头还是 f
+        assert_eq!(velocities("|: 1 f 2 :| 3").unwrap(), [80, 96, 96, 96, 96]);
+    }
+
+    #[test]
+    fn hairpins() {
+        assert_eq!(
+            velocities("p 1 cresc 2 3 4 5 | f 6").unwrap(),
+            [49, 49, 60, 72, 84, 96]
+        );
+        assert_eq!(velocities("ff 1 dim 2 [3 5] p 4").unwrap(), [112, 112, 81, 49]);
+        let err = velocities("1 cresc 2 3").unwrap_err();
+        assert!(err.message.contains("must end at a dynamic marking"));
+        let err = velocities("f 1 cresc 2 p 3").unwrap_err();
+        assert_eq!(err.message, "`cresc` must end at a louder dynamic");
+        let err = velocities("p 1 dim 2 f 3").unwrap_err();
+        assert_eq!(err.message, "`dim` must end at a softer dynamic");
+        let err = velocities("1 cresc 2 dim 3 p 4").unwrap_err();
+        assert!(err.message.starts_with("the previous `cresc` or `dim` has not ended"));
+    }
+
+    #[test]
+    fn key_with_midi() {
+        let strings = RefCell::new(Vec::new());
+        let src = "int main() { Score s(midi = \"a.mid\", key = \"G\"); return 0; }";
+        let err = CompUnitParser::new().parse(&strings, src).unwrap_err();
+        let err = from_parse_error(err, 0);
+        assert!(err.message.starts_with("option `key` cannot be used with `midi`"));
+    }
+
+    #[test]
+    fn mark_words_are_identifiers() {
+        let strings = RefCell::new(Vec::new());
+        let src = "int Fine = 3; int main() { int Coda = Fine + 1; return Segno(Coda); }";
+        assert!(CompUnitParser::new().parse(&strings, src).is_ok());
+    }
+}
@@ -0,0 +1,643 @@
+// 进程内的音频渲染器，取代原来 score_sing 里对 rubberband-r3 和 sox 的 system() 调用。
+// 所有采样在内存里用 f32 表示，每个声道一个 Vec。
+
+use crate::articulation::*;
+use crate::sf2::{self, Zone};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+/// 一段内存中的音频，samples\[c] 是第 c 个声道的采样，取值范围 \[-1, 1]
+#[derive(Clone)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub bits: u16,
+    pub samples: Vec<Vec<f32>>,
+}
+
+impl Wav {
+    pub fn silence(sample_rate: u32, bits: u16, channels: usize, len: usize) -> Wav {
+        Wav {
+            sample_rate,
+            bits,
+            samples: vec![vec![0.0; len]; channels],
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.first().map_or(0, |c| c.len())
+    }
+}
+
+fn bad_wav(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn decode_sample(bytes: &[u8], format: u16, bits: u16) -> f32 {
+    match (format, bits) {
+        (1, 8) => (bytes[0] as f32 - 128.0) / 128.0,
+        (1, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+        (1, 24) => {
+            let v = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+            v as f32 / 8388608.0
+        }
+        (1, 32) => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0,
+        (3, 32) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        _ => unreachable!(),
+    }
+}
+
+/// 解析 RIFF/WAVE 文件，支持 8/16/24/32 位整数 PCM 和 32 位浮点
+pub fn parse_wav(bytes: &[u8]) -> Result<Wav> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(bad_wav("not a RIFF/WAVE file"));
+    }
+    let mut fmt: Option<(u16, usize, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let size = read_u32(bytes, at + 4) as usize;
+        let body = &bytes[at + 8..std::cmp::min(at + 8 + size, bytes.len())];
+        if id == b"fmt " {
+            if body.len() < 16 {
+                return Err(bad_wav("fmt chunk too short"));
+            }
+            let mut format = read_u16(body, 0);
+            if format == 0xfffe && body.len() >= 26 {
+                // WAVE_FORMAT_EXTENSIBLE，真正的格式在 SubFormat GUID 的前两个字节
+                format = read_u16(body, 24);
+            }
+            let channels = read_u16(body, 2) as usize;
+            let sample_rate = read_u32(body, 4);
+            let bits = read_u16(body, 14);
+            fmt = Some((format, channels, sample_rate, bits));
+        } else if id == b"data" {
+            data = Some(body);
+        }
+        at += 8 + size + (size & 1);
+    }
+    let (format, channels, sample_rate, bits) = fmt.ok_or_else(|| bad_wav("missing fmt chunk"))?;
+    let data = data.ok_or_else(|| bad_wav("missing data chunk"))?;
+    let supported = matches!((format, bits), (1, 8) | (1, 16) | (1, 24) | (1, 32) | (3, 32));
+    if !supported || channels == 0 {
+        return Err(bad_wav("unsupported sample format"));
+    }
+    let width = bits as usize / 8;
+    let frames = data.len() / (width * channels);
+    let mut samples = vec![Vec::with_capacity(frames); channels];
+    for frame in data.chunks_exact(width * channels) {
+        for (c, sample) in frame.chunks_exact(width).enumerate() {
+            samples[c].push(decode_sample(sample, format, bits));
+        }
+    }
+    Ok(Wav {
+        sample_rate,
+        bits,
+        samples,
+    })
+}
+
+pub fn read_wav(path: &str) -> Result<Wav> {
+    parse_wav(&fs::read(path)?)
+}
+
+/// 按 wav.bits 编码成整数 PCM 的 WAVE 文件
+pub fn encode_wav(wav: &Wav) -> Vec<u8> {
+    let bits = match wav.bits {
+        8 | 16 | 24 | 32 => wav.bits,
+        _ => 16,
+    };
+    let width = bits as usize / 8;
+    let channels = wav.channels();
+    let data_len = wav.len() * channels * width;
+    let mut out = Vec::with_capacity(44 + data_len);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&(channels as u16).to_le_bytes());
+    out.extend_from_slice(&wav.sample_rate.to_le_bytes());
+    out.extend_from_slice(&(wav.sample_rate * (channels * width) as u32).to_le_bytes());
+    out.extend_from_slice(&((channels * width) as u16).to_le_bytes());
+    out.extend_from_slice(&bits.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data_len as u32).to_le_bytes());
+    for i in 0..wav.len() {
+        for channel in wav.samples.iter() {
+            let v = channel[i].clamp(-1.0, 1.0) as f64;
+            match bits {
+                8 => out.push((v * 127.0 + 128.0).round() as u8),
+                16 => out.extend_from_slice(&((v * 32767.0).round() as i16).to_le_bytes()),
+                24 => out.extend_from_slice(&((v * 8388607.0).round() as i32).to_le_bytes()[0..3]),
+                _ => out.extend_from_slice(&((v * 2147483647.0).round() as i32).to_le_bytes()),
+            }
+        }
+    }
+    out
+}
+
+pub fn write_wav(path: &str, wav: &Wav) -> Result<()> {
+    fs::write(path, encode_wav(wav))
+}
+
+/// 把 src 的声道数变成 channels：单声道复制到每个声道，多声道缩成单声道时取平均
+fn map_channels(src: &[Vec<f32>], channels: usize) -> Vec<Vec<f32>> {
+    if src.len() == channels {
+        return src.to_vec();
+    }
+    if channels == 1 {
+        let len = src[0].len();
+        let mut mono = vec![0.0; len];
+        for channel in src.iter() {
+            for (m, v) in mono.iter_mut().zip(channel.iter()) {
+                *m += v / src.len() as f32;
+            }
+        }
+        return vec![mono];
+    }
+    (0..channels).map(|c| src[c % src.len()].clone()).collect()
+}
+
+/// 把 wav 转成 rate 采样率、channels 声道，音高不变
+pub fn convert(wav: &Wav, rate: u32, channels: usize) -> Wav {
+    let step = wav.sample_rate as f64 / rate as f64;
+    let samples = map_channels(&wav.samples, channels)
+        .iter()
+        .map(|c| if rate == wav.sample_rate { c.clone() } else { resample(c, step) })
+        .collect();
+    Wav {
+        sample_rate: rate,
+        bits: wav.bits,
+        samples,
+    }
+}
+
+/// 限幅器允许的最大幅度，略低于满幅
+const LIMIT: f32 = 0.98;
+
+/// 防削波的限幅器：某个采样超过 LIMIT 时立刻把增益压到刚好不超过，之后在 50ms 左右慢慢恢复。
+/// 所有声道共用一个增益，声像不会变；没有超过 LIMIT 的音频原样不动
+pub fn limit(wav: &mut Wav) {
+    let release = (-1.0 / (0.05 * wav.sample_rate as f32)).exp();
+    let mut gain = 1.0f32;
+    for i in 0..wav.len() {
+        let peak = wav
+            .samples
+            .iter()
+            .filter_map(|c| c.get(i))
+            .fold(0.0f32, |m, v| m.max(v.abs()));
+        let target = if peak > LIMIT { LIMIT / peak } else { 1.0 };
+        gain = if target < gain {
+            target
+        } else {
+            target + (gain - target) * release
+        };
+        if gain < 1.0 {
+            for v in wav.samples.iter_mut().filter_map(|c| c.get_mut(i)) {
+                *v *= gain;
+            }
+        }
+    }
+}
+
+/// 以 step 为步长线性插值重采样；step > 1 时音高升高、长度变短
+fn resample(src: &[f32], step: f64) -> Vec<f32> {
+    if src.is_empty() {
+        return Vec::new();
+    }
+    let len = (src.len() as f64 / step).floor() as usize;
+    let mut out = Vec::with_capacity(len);
+    for i in 0..len {
+        let pos = i as f64 * step;
+        let k = pos as usize;
+        let frac = (pos - k as f64) as f32;
+        let a = src[k];
+        let b = if k + 1 < src.len() { src[k + 1] } else { a };
+        out.push(a + (b - a) * frac);
+    }
+    out
+}
+
+fn sample_at(src: &[f32], at: isize) -> f32 {
+    if at < 0 || at as usize >= src.len() {
+        0.0
+    } else {
+        src[at as usize]
+    }
+}
+
+/// WSOLA 时间伸缩：不改变音高，把每个声道都拉伸/压缩到 out_len 个采样。
+/// 各声道共用同一组分析帧位置，避免声道之间相位漂移。
+fn time_stretch(src: &[Vec<f32>], out_len: usize, sample_rate: u32) -> Vec<Vec<f32>> {
+    let in_len = src.first().map_or(0, |c| c.len());
+    if out_len == 0 || in_len == 0 {
+        return vec![vec![0.0; out_len]; src.len()];
+    }
+    // 帧长约 46ms，50% 重叠的 Hann 窗
+    let win = std::cmp::max(64, (sample_rate as usize / 22) & !1);
+    let hop = win / 2;
+    let tolerance = (hop / 2) as isize;
+    let window: Vec<f32> = (0..win)
+        .map(|i| (0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / win as f64).cos()) as f32)
+        .collect();
+    let mono: Vec<f32> = (0..in_len)
+        .map(|i| src.iter().map(|c| c[i]).sum::<f32>() / src.len() as f32)
+        .collect();
+    let alpha = in_len as f64 / out_len as f64;
+
+    let mut out = vec![vec![0.0f32; out_len + win]; src.len()];
+    let mut weight = vec![0.0f32; out_len + win];
+    let mut prev: isize = 0;
+    let mut k = 0;
+    while k * hop < out_len {
+        let nominal = (k as f64 * hop as f64 * alpha) as isize;
+        let mut best = nominal;
+        if k > 0 {
+            // 在 nominal 附近找与上一帧自然延续最相似的位置
+            let natural = prev + hop as isize;
+            let mut best_score = f32::MIN;
+            let mut delta = -tolerance;
+            while delta <= tolerance {
+                let cand = nominal + delta;
+                let mut score = 0.0;
+                let mut i = 0;
+                while i < hop {
+                    score += sample_at(&mono, cand + i as isize) * sample_at(&mono, natural + i as isize);
+                    i += 4;
+                }
+                if score > best_score {
+                    best_score = score;
+                    best = cand;
+                }
+                delta += 2;
+            }
+        }
+        let base = k * hop;
+        for (c, channel) in src.iter().enumerate() {
+            for i in 0..win {
+                out[c][base + i] += sample_at(channel, best + i as isize) * window[i];
+            }
+        }
+        for i in 0..win {
+            weight[base + i] += window[i];
+        }
+        prev = best;
+        k += 1;
+    }
+    for channel in out.iter_mut() {
+        for (v, w) in channel.iter_mut().zip(weight.iter()) {
+            *v /= w.max(1e-6);
+        }
+        channel.truncate(out_len);
+    }
+    out
+}
+
+/// do（也就是 C4）的频率，内置乐器按它定音
+const DO_HZ: f64 = 261.63;
+
+/// 内置的合成乐器，sing 时可以用名字代替音源文件
+#[derive(Clone, Copy)]
+pub enum Oscillator {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+    Fm,
+    Pluck,
+}
+
+impl Oscillator {
+    pub fn by_name(name: &str) -> Option<Oscillator> {
+        match name {
+            "sine" => Some(Oscillator::Sine),
+            "square" => Some(Oscillator::Square),
+            "saw" => Some(Oscillator::Saw),
+            "triangle" => Some(Oscillator::Triangle),
+            "fm" => Some(Oscillator::Fm),
+            "pluck" => Some(Oscillator::Pluck),
+            _ => None,
+        }
+    }
+
+    /// 合成 half 个半音（相对 C4）、seconds 秒的音，每个声道都一样
+    fn render_note(&self, half: i32, seconds: f64, rate: u32, channels: usize) -> Vec<Vec<f32>> {
+        let len = (seconds * rate as f64).round() as usize;
+        let freq = DO_HZ * 2f64.powf(half as f64 / 12.0);
+        let rate = rate as f64;
+        let samples: Vec<f32> = match self {
+            Oscillator::Pluck => karplus_strong(freq, rate, len),
+            _ => (0..len)
+                .map(|i| {
+                    let t = i as f64 / rate;
+                    let phase = (freq * t).fract();
+                    let v = match self {
+                        Oscillator::Sine => (2.0 * std::f64::consts::PI * phase).sin(),
+                        Oscillator::Square => {
+                            if phase < 0.5 {
+                                1.0
+                            } else {
+                                -1.0
+                            }
+                        }
+                        Oscillator::Saw => 2.0 * phase - 1.0,
+                        Oscillator::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+                        // 调制频率是载波的两倍，调制指数随时间衰减，有点像电钢琴
+                        _ => {
+                            let index = 2.0 * (-3.0 * t).exp();
+                            let modulator = (2.0 * std::f64::consts::PI * 2.0 * freq * t).sin();
+                            (2.0 * std::f64::consts::PI * freq * t + index * modulator).sin()
+                        }
+                    };
+                    (v * 0.3) as f32
+                })
+                .collect(),
+        };
+        vec![samples; channels]
+    }
+}
+
+/// Karplus–Strong 拨弦：一段噪声反复经过平均滤波。噪声用固定种子生成，每次渲染结果相同
+fn karplus_strong(freq: f64, rate: f64, len: usize) -> Vec<f32> {
+    let period = std::cmp::max(2, (rate / freq).round() as usize);
+    let mut seed: u32 = 0x2545_f491;
+    let mut buf: Vec<f32> = (0..period)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed as f32 / u32::MAX as f32 * 2.0 - 1.0) * 0.3
+        })
+        .collect();
+    let mut out = Vec::with_capacity(len);
+    for i in 0..len {
+        let k = i % period;
+        let v = buf[k];
+        out.push(v);
+        buf[k] = 0.996 * 0.5 * (v + buf[(k + 1) % period]);
+    }
+    out
+}
+
+/// sing 用的音源：wav 文件、多采样乐器、内置乐器或者 SoundFont 里的一个音色
+pub enum Source {
+    Sample(Wav),
+    Multi(Vec<(Wav, i32)>),
+    Synth(Oscillator),
+    SoundFont(Vec<Zone>),
+}
+
+impl Source {
+    /// name 是内置乐器的名字时用内置乐器，以 .sf2 结尾时读取 SoundFont 里编号为 preset 的音色，否则读 wav 文件
+    pub fn open(name: &str, preset: i32) -> Result<Source> {
+        if let Some(osc) = Oscillator::by_name(name) {
+            return Ok(Source::Synth(osc));
+        }
+        if name.to_ascii_lowercase().ends_with(".sf2") {
+            return sf2::load(name, preset).map(Source::SoundFont);
+        }
+        read_wav(name).map(Source::Sample)
+    }
+
+    /// samples 是 (wav 文件名, 根音) 的列表
+    pub fn multi(samples: &[(String, i32)]) -> Result<Source> {
+        if samples.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "instrument has no samples"));
+        }
+        let wavs = samples
+            .iter()
+            .map(|(name, root)| read_wav(name).map(|wav| (wav, *root)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Source::Multi(wavs))
+    }
+
+    fn render_note(&self, half: i32, seconds: f64, rate: u32, channels: usize) -> Vec<Vec<f32>> {
+        match self {
+            Source::Sample(src) => render_note(src, half, seconds, rate, channels),
+            Source::Multi(wavs) => {
+                // 挑根音离 half 最近的采样，变调的幅度最小
+                let (src, root) = wavs.iter().min_by_key(|(_, root)| (root - half).abs()).unwrap();
+                render_note(src, half - root, seconds, rate, channels)
+            }
+            Source::Synth(osc) => osc.render_note(half, seconds, rate, channels),
+            Source::SoundFont(zones) => {
+                // C4 是 MIDI 的 60 号音
+                let key = 60 + half;
+                let len = (seconds * rate as f64).round() as usize;
+                vec![sf2::play(sf2::pick(zones, key), key, len, rate); channels]
+            }
+        }
+    }
+}
+
+/// 把音源升高 half 个半音，并伸缩到恰好 seconds 秒，输出格式为 rate 采样率、channels 声道
+pub fn render_note(src: &Wav, half: i32, seconds: f64, rate: u32, channels: usize) -> Vec<Vec<f32>> {
+    let out_len = (seconds * rate as f64).round() as usize;
+    let step = 2f64.powf(half as f64 / 12.0) * src.sample_rate as f64 / rate as f64;
+    let shifted: Vec<Vec<f32>> = map_channels(&src.samples, channels)
+        .iter()
+        .map(|c| resample(c, step))
+        .collect();
+    time_stretch(&shifted, out_len, rate)
+}
+
+/// 一个已经合并了延音线的音符：rest 为 true 时是休止，否则是 halves 里每个音高的音源叠在一起（和弦），
+/// 音量乘上 gain，articulation 是演奏法
+pub struct RenderNote {
+    pub rest: bool,
+    pub halves: Vec<i32>,
+    pub gain: f32,
+    pub articulation: i32,
+    pub seconds: f64,
+}
+
+/// MIDI 力度换算成音量倍数：和 MIDI 一样按平方，mf (80) 是 1
+pub fn velocity_gain(velocity: i32) -> f32 {
+    let v = velocity as f32 / 80.0;
+    v * v
+}
+
+impl RenderNote {
+    /// 这个音占的时间：延长记号多停留一半
+    fn slot_seconds(&self) -> f64 {
+        if self.articulation & FERMATA != 0 {
+            self.seconds * 1.5
+        } else {
+            self.seconds
+        }
+    }
+
+    /// 实际发声的时间：断奏只响一半，保持音延续到下一个音里 50ms
+    fn sound_seconds(&self) -> f64 {
+        let slot = self.slot_seconds();
+        if self.articulation & STACCATO != 0 {
+            slot * 0.5
+        } else if self.articulation & TENUTO != 0 {
+            slot + 0.05
+        } else {
+            slot
+        }
+    }
+}
+
+/// ADSR 包络，时间都以秒为单位
+#[derive(Clone, Copy)]
+pub struct Envelope {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f32,
+    pub release: f64,
+}
+
+impl Envelope {
+    /// 默认只有很短的起音和释音，刚好消掉音符衔接处的爆音
+    pub const DEFAULT: Envelope = Envelope {
+        attack: 0.005,
+        decay: 0.0,
+        sustain: 1.0,
+        release: 0.01,
+    };
+
+    /// a、d、r 是毫秒，s 是百分比
+    pub fn new(a: i32, d: i32, s: i32, r: i32) -> Envelope {
+        Envelope {
+            attack: a.max(0) as f64 / 1000.0,
+            decay: d.max(0) as f64 / 1000.0,
+            sustain: s.clamp(0, 100) as f32 / 100.0,
+            release: r.max(0) as f64 / 1000.0,
+        }
+    }
+
+    /// 按下 t 秒时的音量
+    fn level(&self, t: f64) -> f32 {
+        if t < self.attack {
+            (t / self.attack) as f32
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * ((t - self.attack) / self.decay) as f32
+        } else {
+            self.sustain
+        }
+    }
+
+    /// 音在 off 秒时松开，释音之后到 0，piece 的长度应该是 off + release
+    fn apply(&self, piece: &mut [Vec<f32>], off: f64, rate: u32) {
+        let off_level = self.level(off);
+        for channel in piece.iter_mut() {
+            for (i, v) in channel.iter_mut().enumerate() {
+                let t = i as f64 / rate as f64;
+                *v *= if t < off {
+                    self.level(t)
+                } else {
+                    off_level * (1.0 - ((t - off) / self.release) as f32).max(0.0)
+                };
+            }
+        }
+    }
+}
+
+/// 重音：起音的 80ms 内音量从 1.6 倍降回 1 倍
+fn accent(piece: &mut [Vec<f32>], rate: u32) {
+    let len = (0.08 * rate as f64) as usize;
+    for channel in piece.iter_mut() {
+        for (i, v) in channel.iter_mut().take(len).enumerate() {
+            *v *= 1.0 + 0.6 * (1.0 - i as f32 / len as f32);
+        }
+    }
+}
+
+/// 用音源 src 依次渲染 notes 并拼接，写到 toname。每个音从它的开始时间起混进输出，
+/// 这样释音和保持音可以和下一个音重叠，相邻的音交叉淡化
+pub fn render(
+    src: &Source,
+    toname: &str,
+    notes: &[RenderNote],
+    envelope: Envelope,
+    rate: u32,
+    bits: u16,
+    channels: usize,
+) -> Result<()> {
+    let mut out = Wav::silence(rate, bits, channels, 0);
+    let mut start = 0;
+    for note in notes.iter() {
+        let end = start + (note.slot_seconds() * rate as f64).round() as usize;
+        if !note.rest {
+            let off = note.sound_seconds();
+            let seconds = off + envelope.release;
+            let len = (seconds * rate as f64).round() as usize;
+            let mut piece = vec![vec![0.0; len]; channels];
+            // 和弦的各个音直接相加，叠加出来超过满幅的部分最后由 limit 压下去
+            for &half in note.halves.iter() {
+                let voice = src.render_note(half, seconds, rate, channels);
+                for (channel, samples) in piece.iter_mut().zip(voice) {
+                    for (v, s) in channel.iter_mut().zip(samples) {
+                        *v += s * note.gain;
+                    }
+                }
+            }
+            envelope.apply(&mut piece, off, rate);
+            if note.articulation & ACCENT != 0 {
+                accent(&mut piece, rate);
+            }
+            for (channel, samples) in out.samples.iter_mut().zip(piece) {
+                if channel.len() < start + samples.len() {
+                    channel.resize(start + samples.len(), 0.0);
+                }
+                for (v, s) in channel[start..].iter_mut().zip(samples) {
+                    *v += s;
+                }
+            }
+        }
+        start = end;
+    }
+    // 最后一个音延续出去的部分不要，总长度和乐谱一致
+    for channel in out.samples.iter_mut() {
+        channel.resize(start, 0.0);
+    }
+    limit(&mut out);
+    write_wav(toname, &out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chord_stays_below_full_scale() {
+        let path = std::env::temp_dir().join("sysylive_chord_test.wav");
+        let path = path.to_str().unwrap();
+        let chord = RenderNote {
+            rest: false,
+            halves: vec![0, 4, 7, 12],
+            gain: velocity_gain(127),
+            articulation: ACCENT,
+            seconds: 1.0,
+        };
+        let src = Source::open("square", 0).unwrap();
+        render(&src, path, &[chord], Envelope::DEFAULT, 8000, 16, 1).unwrap();
+        let wav = read_wav(path).unwrap();
+        let _ = fs::remove_file(path);
+        let peak = wav.samples[0].iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        assert!(peak > 0.5 && peak <= LIMIT + 1e-3, "peak {}", peak);
+    }
+
+    #[test]
+    fn limit_leaves_quiet_audio_alone() {
+        let mut wav = Wav::silence(8000, 16, 2, 4);
+        wav.samples[0] = vec![0.5, -0.9, 0.2, 0.0];
+        limit(&mut wav);
+        assert_eq!(wav.samples[0], [0.5, -0.9, 0.2, 0.0]);
+    }
+}
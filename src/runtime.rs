@@ -0,0 +1,709 @@
+// SysY Live 的运行时库，编译成 libsysylive.a 后与编译出来的程序链接。
+// 导出的函数与 libfuncs.rs 里的签名表一一对应，由 build.rs 生成的检查保证。
+// 所有的 note/bar/score/track 在程序里都用 int 编号表示。
+
+mod articulation;
+mod midi;
+mod render;
+mod sf2;
+
+use midi::{MidiNote, PPQ};
+use render::*;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+
+// 见 build.rs：libfuncs.rs 里每个函数都必须在这里以相同签名导出
+include!(concat!(env!("OUT_DIR"), "/runtime_abi.rs"));
+
+#[derive(Clone)]
+struct Note {
+    rest_or_tie: i32, // rest 1 tie 2
+    half: i32,        // do+几个半音
+    chord: Vec<i32>,  // 和弦里除了 half 以外的其他音
+    duration_beat: f64,
+    velocity: i32,     // MIDI 力度，80 (mf) 就是音源原本的音量
+    articulation: i32, // 演奏法，见 articulation.rs 里的 STACCATO 等
+}
+
+#[derive(Clone)]
+struct Bar {
+    notes: Vec<usize>,
+    bpm: i32,
+}
+
+impl Bar {
+    const fn new() -> Bar {
+        Bar {
+            notes: Vec::new(),
+            bpm: 100,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Score {
+    bars: Vec<Bar>,
+    envelope: Envelope,
+}
+
+struct Track {
+    name: String,
+}
+
+/// 多采样乐器：每个采样文件和它的根音（相对 C4 的半音数）
+struct Instrument {
+    samples: Vec<(String, i32)>,
+}
+
+// 注意 note 是 immutable 的：不能修改已经存在的 note，只能新建一个 note
+struct Runtime {
+    notes: Vec<Note>,
+    bars: Vec<Bar>,
+    scores: Vec<Score>,
+    tracks: Vec<Track>,
+    instruments: Vec<Instrument>,
+    timer: Option<Instant>,
+}
+
+static RUNTIME: Mutex<Runtime> = Mutex::new(Runtime {
+    notes: Vec::new(),
+    bars: Vec::new(),
+    scores: Vec::new(),
+    tracks: Vec::new(),
+    instruments: Vec::new(),
+    timer: None,
+});
+
+fn with_runtime<R>(f: impl FnOnce(&mut Runtime) -> R) -> R {
+    let mut rt = RUNTIME.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut rt)
+}
+
+/// 把 0 结尾的 int 数组转成字符串
+///
+/// # Safety
+/// name 必须指向一个以 0 结尾的 int 数组
+unsafe fn read_name(name: *const i32) -> String {
+    let mut ret = String::new();
+    let mut i = 0;
+    while *name.add(i) != 0 {
+        ret.push(char::from_u32(*name.add(i) as u32).unwrap_or('?'));
+        i += 1;
+    }
+    ret
+}
+
+impl Runtime {
+    fn push_note(&mut self, note: Note) -> i32 {
+        self.notes.push(note);
+        self.notes.len() as i32 - 1
+    }
+
+    /// 把 bar 里所有音符升高 semitones 个半音，休止和延音不变
+    fn inc_pitch(&mut self, bar: &mut Bar, semitones: i32) {
+        for i in 0..bar.notes.len() {
+            let tmp = self.notes[bar.notes[i]].clone();
+            if tmp.rest_or_tie == 0 {
+                let id = self.push_note(Note {
+                    half: tmp.half + semitones,
+                    chord: tmp.chord.iter().map(|h| h + semitones).collect(),
+                    ..tmp
+                });
+                bar.notes[i] = id as usize;
+            }
+        }
+    }
+
+    fn beats(&self, bar: &Bar) -> f64 {
+        bar.notes.iter().map(|&n| self.notes[n].duration_beat).sum()
+    }
+
+    /// 展开乐谱并合并延音线，得到渲染器需要的音符序列
+    fn render_notes(&self, x: usize) -> Vec<RenderNote> {
+        let mut note_ids: Vec<(i32, usize)> = Vec::new(); // (bpm, note_id)
+        for bar in self.scores[x].bars.iter() {
+            for &note in bar.notes.iter() {
+                note_ids.push((bar.bpm, note));
+            }
+        }
+        let mut ret = Vec::new();
+        let mut cur_dur = 0.0;
+        let mut lasthalves = vec![0];
+        let mut lastvelocity = 80;
+        let mut lastarticulation = 0;
+        let mut lastrest = false;
+        for i in 0..note_ids.len() {
+            let (bpm, note_id) = note_ids[i];
+            let note = &self.notes[note_id];
+            let needs_out = i + 1 == note_ids.len() || self.notes[note_ids[i + 1].1].rest_or_tie != 2;
+            cur_dur += note.duration_beat * 60.0 / bpm as f64;
+            if note.rest_or_tie == 0 {
+                lasthalves = vec![note.half];
+                lasthalves.extend(note.chord.iter());
+                lastvelocity = note.velocity;
+            }
+            if note.rest_or_tie == 1 {
+                lastrest = true;
+            }
+            // 延音线上任何一个音的演奏法都作用于整个音
+            lastarticulation |= note.articulation;
+            if needs_out {
+                ret.push(RenderNote {
+                    rest: lastrest,
+                    halves: std::mem::replace(&mut lasthalves, vec![0]),
+                    gain: velocity_gain(std::mem::replace(&mut lastvelocity, 80)),
+                    articulation: std::mem::replace(&mut lastarticulation, 0),
+                    seconds: cur_dur,
+                });
+                cur_dur = 0.0;
+                lastrest = false;
+            }
+        }
+        ret
+    }
+
+    /// 乐谱 x 的速度变化 (tick, bpm) 和合并延音线后的音符，用于导出 MIDI
+    fn midi_notes(&self, x: usize) -> (Vec<(u32, i32)>, Vec<MidiNote>) {
+        let mut tempos: Vec<(u32, i32)> = Vec::new();
+        let mut notes = Vec::new();
+        let mut beat = 0.0;
+        // 正在发声的音：开始的 tick、音高和力度
+        let mut sounding: Option<(u32, Vec<i32>, i32)> = None;
+        let tick = |beat: f64| (beat * PPQ as f64).round() as u32;
+        let mut finish = |sounding: Option<(u32, Vec<i32>, i32)>, end: u32| {
+            if let Some((start, halves, velocity)) = sounding {
+                for half in halves {
+                    notes.push(MidiNote {
+                        start,
+                        end,
+                        key: (60 + half).clamp(0, 127) as u8,
+                        velocity: velocity.clamp(1, 127) as u8,
+                    });
+                }
+            }
+        };
+        for bar in self.scores[x].bars.iter() {
+            if tempos.last().is_none_or(|&(_, bpm)| bpm != bar.bpm) {
+                tempos.push((tick(beat), bar.bpm));
+            }
+            for &note_id in bar.notes.iter() {
+                let note = &self.notes[note_id];
+                if note.rest_or_tie != 2 {
+                    finish(sounding.take(), tick(beat));
+                }
+                if note.rest_or_tie == 0 {
+                    let mut halves = vec![note.half];
+                    halves.extend(note.chord.iter());
+                    sounding = Some((tick(beat), halves, note.velocity));
+                }
+                beat += note.duration_beat;
+            }
+        }
+        finish(sounding, tick(beat));
+        (tempos, notes)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn newnote_rest(rest: i32, fz: i32, fm: i32) -> i32 {
+    with_runtime(|rt| {
+        rt.push_note(Note {
+            rest_or_tie: rest,
+            half: 0,
+            chord: Vec::new(),
+            duration_beat: fz as f64 / fm as f64,
+            velocity: 80,
+            articulation: 0,
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn newnote(half: i32, fz: i32, fm: i32, velocity: i32) -> i32 {
+    with_runtime(|rt| {
+        rt.push_note(Note {
+            rest_or_tie: 0,
+            half,
+            chord: Vec::new(),
+            duration_beat: fz as f64 / fm as f64,
+            velocity: velocity.clamp(1, 127),
+            articulation: 0,
+        })
+    })
+}
+
+/// 新建一个音符：在 note 的基础上再加一个音高 half，组成和弦（note 本身不变）
+#[no_mangle]
+pub extern "C" fn note_add_pitch(note: i32, half: i32) -> i32 {
+    with_runtime(|rt| {
+        let mut tmp = rt.notes[note as usize].clone();
+        tmp.chord.push(half);
+        rt.push_note(tmp)
+    })
+}
+
+/// 新建一个音符：在 note 的基础上加上演奏法 flags（note 本身不变）
+#[no_mangle]
+pub extern "C" fn note_articulate(note: i32, flags: i32) -> i32 {
+    with_runtime(|rt| {
+        let mut tmp = rt.notes[note as usize].clone();
+        tmp.articulation |= flags;
+        rt.push_note(tmp)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn newbar() -> i32 {
+    with_runtime(|rt| {
+        rt.bars.push(Bar::new());
+        rt.bars.len() as i32 - 1
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn bar_copy(x: i32, y: i32) {
+    with_runtime(|rt| rt.bars[x as usize] = rt.bars[y as usize].clone())
+}
+
+#[no_mangle]
+pub extern "C" fn bar_setbpm(x: i32, bpm: i32) {
+    with_runtime(|rt| rt.bars[x as usize].bpm = bpm)
+}
+
+#[no_mangle]
+pub extern "C" fn bar_push(x: i32, note: i32) {
+    with_runtime(|rt| rt.bars[x as usize].notes.push(note as usize))
+}
+
+#[no_mangle]
+pub extern "C" fn bar_inc_pitch(x: i32, semitones: i32) {
+    with_runtime(|rt| {
+        let mut bar = rt.bars[x as usize].clone();
+        rt.inc_pitch(&mut bar, semitones);
+        rt.bars[x as usize] = bar;
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn bar_set_duration(x: i32, len_ms: i32) {
+    with_runtime(|rt| {
+        let sum = rt.beats(&rt.bars[x as usize]);
+        if sum == 0.0 {
+            return;
+        }
+        // sum 拍对应 len_ms 毫秒
+        rt.bars[x as usize].bpm = (sum * 60000.0 / len_ms as f64) as i32;
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn newscore() -> i32 {
+    with_runtime(|rt| {
+        rt.scores.push(Score {
+            bars: Vec::new(),
+            envelope: Envelope::DEFAULT,
+        });
+        rt.scores.len() as i32 - 1
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn score_copy(x: i32, y: i32) {
+    with_runtime(|rt| rt.scores[x as usize] = rt.scores[y as usize].clone())
+}
+
+#[no_mangle]
+pub extern "C" fn score_push(x: i32, y: i32) {
+    with_runtime(|rt| {
+        let bar = rt.bars[y as usize].clone();
+        rt.scores[x as usize].bars.push(bar);
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn score_append(x: i32, y: i32) {
+    with_runtime(|rt| {
+        let bars = rt.scores[y as usize].bars.clone();
+        rt.scores[x as usize].bars.extend(bars);
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn score_replace(x: i32, k: i32, id: i32) {
+    with_runtime(|rt| rt.scores[x as usize].bars[k as usize] = rt.bars[id as usize].clone())
+}
+
+#[no_mangle]
+pub extern "C" fn score_setbpm(x: i32, bpm: i32) {
+    with_runtime(|rt| {
+        for bar in rt.scores[x as usize].bars.iter_mut() {
+            bar.bpm = bpm;
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn score_inc_pitch(x: i32, semitones: i32) {
+    with_runtime(|rt| {
+        let mut bars = std::mem::take(&mut rt.scores[x as usize].bars);
+        for bar in bars.iter_mut() {
+            rt.inc_pitch(bar, semitones);
+        }
+        rt.scores[x as usize].bars = bars;
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn score_set_duration(x: i32, len_ms: i32) {
+    let sum = with_runtime(|rt| rt.scores[x as usize].bars.iter().map(|b| rt.beats(b)).sum::<f64>());
+    if sum == 0.0 {
+        return;
+    }
+    score_setbpm(x, (sum * 60000.0 / len_ms as f64) as i32);
+}
+
+/// 每个音的包络：起音 a 毫秒、衰减 d 毫秒、保持在 s% 的音量、释音 r 毫秒（和下一个音交叉淡化）
+#[no_mangle]
+pub extern "C" fn score_set_envelope(x: i32, a: i32, d: i32, s: i32, r: i32) {
+    with_runtime(|rt| rt.scores[x as usize].envelope = Envelope::new(a, d, s, r))
+}
+
+/// 用 name 作为音源（wav 文件、.sf2 音色库，或者 sine、pluck 等内置乐器的名字），按乐谱 x 生成 srate 采样率、bytes 位、channels 声道的音频 toname
+///
+/// # Safety
+/// name 与 toname 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn score_sing(x: i32, name: *mut i32, toname: *mut i32, srate: i32, bytes: i32, channels: i32) {
+    score_sing_preset(x, name, 0, toname, srate, bytes, channels)
+}
+
+/// 同 score_sing，name 是 .sf2 音色库时用其中编号为 preset 的音色
+///
+/// # Safety
+/// name 与 toname 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn score_sing_preset(
+    x: i32,
+    name: *mut i32,
+    preset: i32,
+    toname: *mut i32,
+    srate: i32,
+    bytes: i32,
+    channels: i32,
+) {
+    let name = read_name(name);
+    let toname = read_name(toname);
+    let (notes, envelope) = with_runtime(|rt| (rt.render_notes(x as usize), rt.scores[x as usize].envelope));
+    let channels = channels.max(1) as usize;
+    let result = Source::open(&name, preset)
+        .and_then(|src| render(&src, &toname, &notes, envelope, srate as u32, bytes as u16, channels));
+    if let Err(err) = result {
+        eprintln!("score_sing: cannot render {} from {}: {}", toname, name, err);
+    }
+}
+
+/// 同 score_sing，用多采样乐器 inst 作为音源
+///
+/// # Safety
+/// toname 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn score_sing_instrument(x: i32, inst: i32, toname: *mut i32, srate: i32, bytes: i32, channels: i32) {
+    let toname = read_name(toname);
+    let (notes, envelope, samples) = with_runtime(|rt| {
+        let score = &rt.scores[x as usize];
+        (rt.render_notes(x as usize), score.envelope, rt.instruments[inst as usize].samples.clone())
+    });
+    let channels = channels.max(1) as usize;
+    let result = Source::multi(&samples)
+        .and_then(|src| render(&src, &toname, &notes, envelope, srate as u32, bytes as u16, channels));
+    if let Err(err) = result {
+        eprintln!("score_sing: cannot render {}: {}", toname, err);
+    }
+}
+
+/// 把乐谱 x 导出成 Type 1 的标准 MIDI 文件 name，每个小节的 bpm 写成速度事件
+///
+/// # Safety
+/// name 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn score_export_midi(x: i32, name: *mut i32) {
+    let name = read_name(name);
+    let (tempos, notes) = with_runtime(|rt| rt.midi_notes(x as usize));
+    report("score_export_midi", &name, midi::write_smf(&name, &tempos, &notes));
+}
+
+#[no_mangle]
+pub extern "C" fn newinstrument() -> i32 {
+    with_runtime(|rt| {
+        rt.instruments.push(Instrument { samples: Vec::new() });
+        rt.instruments.len() as i32 - 1
+    })
+}
+
+/// 给乐器 x 添加采样 name，它的音高是 C4 往上 root 个半音
+///
+/// # Safety
+/// name 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn instrument_add_sample(x: i32, name: *mut i32, root: i32) {
+    let name = read_name(name);
+    with_runtime(|rt| rt.instruments[x as usize].samples.push((name, root)));
+}
+
+#[no_mangle]
+pub extern "C" fn newtrack() -> i32 {
+    with_runtime(|rt| {
+        rt.tracks.push(Track { name: String::new() });
+        rt.tracks.len() as i32 - 1
+    })
+}
+
+fn track_name(x: i32) -> String {
+    with_runtime(|rt| rt.tracks[x as usize].name.clone())
+}
+
+fn report(op: &str, name: &str, res: io::Result<()>) {
+    if let Err(err) = res {
+        eprintln!("{}: {}: {}", op, name, err);
+    }
+}
+
+/// load 就是直接把名字绑定上去
+///
+/// # Safety
+/// name 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn track_load(x: i32, name: *mut i32) {
+    let name = read_name(name);
+    with_runtime(|rt| rt.tracks[x as usize].name = name);
+}
+
+/// 把音频 name1 复制到 name2，并把 x 绑定到 name2
+///
+/// # Safety
+/// name1 与 name2 必须是以 0 结尾的 int 数组
+#[no_mangle]
+pub unsafe extern "C" fn track_copy(x: i32, name1: *mut i32, name2: *mut i32) {
+    let name1 = read_name(name1);
+    let name2 = read_name(name2);
+    report("track_copy", &name1, fs::copy(&name1, &name2).map(|_| ()));
+    with_runtime(|rt| rt.tracks[x as usize].name = name2);
+}
+
+/// x += y，y 会先被转换成 x 的采样率和声道数
+#[no_mangle]
+pub extern "C" fn track_append(x: i32, y: i32) {
+    let name1 = track_name(x);
+    let name2 = track_name(y);
+    let res = (|| {
+        let mut a = read_wav(&name1)?;
+        let b = convert(&read_wav(&name2)?, a.sample_rate, a.channels());
+        for (channel, samples) in a.samples.iter_mut().zip(b.samples) {
+            channel.extend(samples);
+        }
+        write_wav(&name1, &a)
+    })();
+    report("track_append", &name1, res);
+}
+
+/// 在 x 末尾加上 len_ms 毫秒的沉默；x 的文件还不存在时按给定格式新建
+#[no_mangle]
+pub extern "C" fn track_append_silence(x: i32, len_ms: i32, srate: i32, bytes: i32, channels: i32) {
+    let name = track_name(x);
+    let res = (|| {
+        let mut a = match read_wav(&name) {
+            Ok(a) => a,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Wav::silence(srate as u32, bytes as u16, channels.max(1) as usize, 0)
+            }
+            Err(err) => return Err(err),
+        };
+        let len = (len_ms as f64 / 1000.0 * a.sample_rate as f64).round() as usize;
+        for channel in a.samples.iter_mut() {
+            channel.resize(channel.len() + len, 0.0);
+        }
+        write_wav(&name, &a)
+    })();
+    report("track_append_silence", &name, res);
+}
+
+#[no_mangle]
+pub extern "C" fn track_set_volume(x: i32, fz: i32, fm: i32) {
+    let name = track_name(x);
+    let res = (|| {
+        let mut a = read_wav(&name)?;
+        let vol = fz as f32 / fm as f32;
+        for channel in a.samples.iter_mut() {
+            for v in channel.iter_mut() {
+                *v *= vol;
+            }
+        }
+        write_wav(&name, &a)
+    })();
+    report("track_set_volume", &name, res);
+}
+
+/// 把 y 混音进 x，结果长度取两者中较长的
+#[no_mangle]
+pub extern "C" fn track_stack(x: i32, y: i32) {
+    track_stack_at(x, y, 0)
+}
+
+/// 把 y 从 x 的第 offset_ms 毫秒开始混音进 x，x 不够长时在末尾补静音，声道数取两者中多的。
+/// 逐个采样相加之后过一遍限幅器，叠加起来超过满幅的地方不会被截断
+#[no_mangle]
+pub extern "C" fn track_stack_at(x: i32, y: i32, offset_ms: i32) {
+    let name1 = track_name(x);
+    let name2 = track_name(y);
+    let res = (|| {
+        let mut a = read_wav(&name1)?;
+        let b = read_wav(&name2)?;
+        // 声道多的一方决定结果的声道数，这样 pan 过的音轨叠到单声道上也保留声像
+        if a.channels() < b.channels() {
+            a = convert(&a, a.sample_rate, b.channels());
+        }
+        let b = convert(&b, a.sample_rate, a.channels());
+        let offset = (offset_ms.max(0) as f64 / 1000.0 * a.sample_rate as f64).round() as usize;
+        for (channel, samples) in a.samples.iter_mut().zip(b.samples) {
+            if channel.len() < offset + samples.len() {
+                channel.resize(offset + samples.len(), 0.0);
+            }
+            for (v, s) in channel[offset..].iter_mut().zip(samples) {
+                *v += s;
+            }
+        }
+        limit(&mut a);
+        write_wav(&name1, &a)
+    })();
+    report("track_stack_at", &name1, res);
+}
+
+/// 声像：percent 从 -100（只剩左声道）到 100（只剩右声道），0 不变。单声道的音频先变成双声道
+#[no_mangle]
+pub extern "C" fn track_pan(x: i32, percent: i32) {
+    let name = track_name(x);
+    let res = (|| {
+        let mut a = read_wav(&name)?;
+        if a.channels() == 1 {
+            a = convert(&a, a.sample_rate, 2);
+        }
+        let pan = percent.clamp(-100, 100) as f32 / 100.0;
+        let gains = [(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)];
+        for (channel, gain) in a.samples.iter_mut().zip(gains) {
+            for v in channel.iter_mut() {
+                *v *= gain;
+            }
+        }
+        write_wav(&name, &a)
+    })();
+    report("track_pan", &name, res);
+}
+
+/// 音量增加 db 分贝（可以是负数），放大后超过满幅的地方由限幅器压住
+#[no_mangle]
+pub extern "C" fn track_gain_db(x: i32, db: i32) {
+    let name = track_name(x);
+    let res = (|| {
+        let mut a = read_wav(&name)?;
+        let gain = 10f32.powf(db as f32 / 20.0);
+        for channel in a.samples.iter_mut() {
+            for v in channel.iter_mut() {
+                *v *= gain;
+            }
+        }
+        limit(&mut a);
+        write_wav(&name, &a)
+    })();
+    report("track_gain_db", &name, res);
+}
+
+// 下面是 SysY 标准库
+
+fn read_byte() -> Option<u8> {
+    let mut buf = [0u8];
+    match io::stdin().lock().read(&mut buf) {
+        Ok(1) => Some(buf[0]),
+        _ => None,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn getint() -> i32 {
+    let mut c = read_byte();
+    while c.is_some_and(|c| c.is_ascii_whitespace()) {
+        c = read_byte();
+    }
+    let mut neg = false;
+    if c == Some(b'-') || c == Some(b'+') {
+        neg = c == Some(b'-');
+        c = read_byte();
+    }
+    let mut ret: i32 = 0;
+    while let Some(d) = c.filter(|c| c.is_ascii_digit()) {
+        ret = ret.wrapping_mul(10).wrapping_add((d - b'0') as i32);
+        c = read_byte();
+    }
+    if neg {
+        -ret
+    } else {
+        ret
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn getch() -> i32 {
+    read_byte().map_or(-1, |c| c as i32)
+}
+
+/// 先读入长度 n，再读入 n 个整数，返回 n
+///
+/// # Safety
+/// a 至少要能放下读入的 n 个整数
+#[no_mangle]
+pub unsafe extern "C" fn getarray(a: *mut i32) -> i32 {
+    let n = getint();
+    for i in 0..n.max(0) as usize {
+        *a.add(i) = getint();
+    }
+    n
+}
+
+#[no_mangle]
+pub extern "C" fn putint(x: i32) {
+    print!("{}", x);
+    let _ = io::stdout().flush();
+}
+
+#[no_mangle]
+pub extern "C" fn putch(x: i32) {
+    let _ = io::stdout().write_all(&[x as u8]);
+    let _ = io::stdout().flush();
+}
+
+/// 输出 "n: a\[0] a\[1] ..." 并换行
+///
+/// # Safety
+/// a 至少要有 n 个元素
+#[no_mangle]
+pub unsafe extern "C" fn putarray(n: i32, a: *mut i32) {
+    let mut line = format!("{}:", n);
+    for i in 0..n.max(0) as usize {
+        line += &format!(" {}", *a.add(i));
+    }
+    println!("{}", line);
+    let _ = io::stdout().flush();
+}
+
+#[no_mangle]
+pub extern "C" fn starttime() {
+    with_runtime(|rt| rt.timer = Some(Instant::now()));
+}
+
+#[no_mangle]
+pub extern "C" fn stoptime() {
+    if let Some(start) = with_runtime(|rt| rt.timer.take()) {
+        eprintln!("Timer: {}ms", start.elapsed().as_millis());
+    }
+}
@@ -0,0 +1,424 @@
+// SoundFont 2 音色库的读取。只取出一个 preset 用到的采样和它们的音域、根音、循环点，
+// 渲染时每个音挑音域最合适的采样再变调，比拿一个采样拉伸好几个八度自然得多。
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+/// 一个采样区域：key_lo..=key_hi 之间的音用这个采样
+pub struct Zone {
+    pub key_lo: i32,
+    pub key_hi: i32,
+    pub root_key: i32,
+    pub tune: f64, // 额外的音高修正，以半音为单位
+    pub sample_rate: u32,
+    pub data: Vec<f32>,
+    pub looped: bool,
+    pub loop_start: usize,
+    pub loop_end: usize,
+}
+
+fn bad_sf2(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad SoundFont: {}", msg))
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// 遍历 RIFF 里 at..end 之间的子块，返回 (id, 内容)
+fn chunks(bytes: &[u8], mut at: usize, end: usize) -> Vec<(&[u8], &[u8])> {
+    let mut ret = Vec::new();
+    while at + 8 <= end {
+        let size = read_u32(bytes, at + 4) as usize;
+        let body_end = std::cmp::min(at + 8 + size, end);
+        ret.push((&bytes[at..at + 4], &bytes[at + 8..body_end]));
+        at += 8 + size + (size & 1);
+    }
+    ret
+}
+
+// 用到的生成器编号
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const COARSE_TUNE: u16 = 51;
+const FINE_TUNE: u16 = 52;
+const SAMPLE_ID: u16 = 53;
+const SAMPLE_MODES: u16 = 54;
+const OVERRIDING_ROOT_KEY: u16 = 58;
+
+#[derive(Clone, Copy, Default)]
+struct Gens {
+    key_range: Option<(i32, i32)>,
+    coarse_tune: Option<i32>,
+    fine_tune: Option<i32>,
+    sample_modes: Option<u16>,
+    root_key: Option<i32>,
+    link: Option<u16>, // preset 里是 instrument，instrument 里是 sampleID
+}
+
+impl Gens {
+    fn set(&mut self, oper: u16, amount: &[u8]) {
+        let value = i16::from_le_bytes([amount[0], amount[1]]) as i32;
+        match oper {
+            KEY_RANGE => self.key_range = Some((amount[0] as i32, amount[1] as i32)),
+            COARSE_TUNE => self.coarse_tune = Some(value),
+            FINE_TUNE => self.fine_tune = Some(value),
+            SAMPLE_MODES => self.sample_modes = Some(value as u16),
+            OVERRIDING_ROOT_KEY => self.root_key = Some(value),
+            INSTRUMENT | SAMPLE_ID => self.link = Some(value as u16),
+            _ => {}
+        }
+    }
+
+    /// 没有设置的生成器用全局区域的
+    fn or(self, global: Gens) -> Gens {
+        Gens {
+            key_range: self.key_range.or(global.key_range),
+            coarse_tune: self.coarse_tune.or(global.coarse_tune),
+            fine_tune: self.fine_tune.or(global.fine_tune),
+            sample_modes: self.sample_modes.or(global.sample_modes),
+            root_key: self.root_key.or(global.root_key),
+            link: self.link,
+        }
+    }
+}
+
+/// 按 bag 表把 gen 表分成一个个区域；没有 link 生成器的第一个区域是全局区域
+fn zones(bags: &[u8], gens: &[u8], from: usize, to: usize) -> (Gens, Vec<Gens>) {
+    let mut global = Gens::default();
+    let mut ret = Vec::new();
+    for b in from..to {
+        if (b + 1) * 4 + 4 > bags.len() {
+            break;
+        }
+        let gen_from = read_u16(bags, b * 4) as usize;
+        let gen_to = read_u16(bags, (b + 1) * 4) as usize;
+        let mut zone = Gens::default();
+        for g in gen_from..gen_to {
+            if g * 4 + 4 > gens.len() {
+                break;
+            }
+            zone.set(read_u16(gens, g * 4), &gens[g * 4 + 2..g * 4 + 4]);
+        }
+        if zone.link.is_none() {
+            if b == from {
+                global = zone;
+            }
+        } else {
+            ret.push(zone);
+        }
+    }
+    (global, ret)
+}
+
+/// 读取 path 里编号为 preset 的音色（优先 bank 0）
+pub fn load(path: &str, preset: i32) -> Result<Vec<Zone>> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"sfbk" {
+        return Err(bad_sf2("not a RIFF/sfbk file"));
+    }
+    let mut smpl: &[u8] = &[];
+    let mut pdta: Vec<(&[u8], &[u8])> = Vec::new();
+    for (id, body) in chunks(&bytes, 12, bytes.len()) {
+        if id == b"LIST" && body.len() >= 4 {
+            let offset = body.as_ptr() as usize - bytes.as_ptr() as usize;
+            let sub = chunks(&bytes, offset + 4, offset + body.len());
+            match &body[0..4] {
+                b"sdta" => {
+                    if let Some((_, data)) = sub.iter().find(|(id, _)| *id == b"smpl") {
+                        smpl = data;
+                    }
+                }
+                b"pdta" => pdta = sub,
+                _ => {}
+            }
+        }
+    }
+    let table = |name: &[u8]| -> Result<&[u8]> {
+        pdta.iter()
+            .find(|(id, _)| *id == name)
+            .map(|(_, body)| *body)
+            .ok_or_else(|| bad_sf2(&format!("missing {} chunk", String::from_utf8_lossy(name))))
+    };
+    let (phdr, pbag, pgen) = (table(b"phdr")?, table(b"pbag")?, table(b"pgen")?);
+    let (inst, ibag, igen) = (table(b"inst")?, table(b"ibag")?, table(b"igen")?);
+    let shdr = table(b"shdr")?;
+
+    // 最后一条是结束标记
+    let presets = (phdr.len() / 38).saturating_sub(1);
+    let found = (0..presets)
+        .filter(|&p| read_u16(phdr, p * 38 + 20) as i32 == preset)
+        .min_by_key(|&p| read_u16(phdr, p * 38 + 22));
+    let p = found.ok_or_else(|| bad_sf2(&format!("no preset {}", preset)))?;
+    let (pglobal, pzones) = zones(
+        pbag,
+        pgen,
+        read_u16(phdr, p * 38 + 24) as usize,
+        read_u16(phdr, (p + 1) * 38 + 24) as usize,
+    );
+
+    let mut ret = Vec::new();
+    let instruments = (inst.len() / 22).saturating_sub(1);
+    let samples = (shdr.len() / 46).saturating_sub(1);
+    for pzone in pzones.iter().map(|z| z.or(pglobal)) {
+        let i = pzone.link.unwrap() as usize;
+        if i >= instruments {
+            continue;
+        }
+        let (iglobal, izones) = zones(
+            ibag,
+            igen,
+            read_u16(inst, i * 22 + 20) as usize,
+            read_u16(inst, (i + 1) * 22 + 20) as usize,
+        );
+        for izone in izones.iter().map(|z| z.or(iglobal)) {
+            let s = izone.link.unwrap() as usize;
+            if s >= samples {
+                continue;
+            }
+            let header = &shdr[s * 46..s * 46 + 46];
+            let start = read_u32(header, 20) as usize;
+            let end = std::cmp::min(read_u32(header, 24) as usize, smpl.len() / 2);
+            if start >= end {
+                continue;
+            }
+            let data: Vec<f32> = (start..end)
+                .map(|k| i16::from_le_bytes([smpl[k * 2], smpl[k * 2 + 1]]) as f32 / 32768.0)
+                .collect();
+            // 音域取 preset 和 instrument 两层的交集
+            let (plo, phi) = pzone.key_range.unwrap_or((0, 127));
+            let (ilo, ihi) = izone.key_range.unwrap_or((0, 127));
+            let correction = header[41] as i8 as f64 / 100.0;
+            let coarse = izone.coarse_tune.unwrap_or(0) + pzone.coarse_tune.unwrap_or(0);
+            let fine = izone.fine_tune.unwrap_or(0) + pzone.fine_tune.unwrap_or(0);
+            let loop_start = (read_u32(header, 28) as usize).saturating_sub(start);
+            let loop_end = (read_u32(header, 32) as usize).saturating_sub(start);
+            ret.push(Zone {
+                key_lo: plo.max(ilo),
+                key_hi: phi.min(ihi),
+                root_key: izone.root_key.filter(|&k| k >= 0).unwrap_or(header[40] as i32),
+                tune: coarse as f64 + fine as f64 / 100.0 + correction,
+                sample_rate: read_u32(header, 36),
+                looped: matches!(izone.sample_modes, Some(1) | Some(3))
+                    && loop_start < loop_end
+                    && loop_end <= data.len(),
+                data,
+                loop_start,
+                loop_end,
+            });
+        }
+    }
+    if ret.is_empty() {
+        return Err(bad_sf2(&format!("preset {} has no samples", preset)));
+    }
+    Ok(ret)
+}
+
+/// 音域包含 key 的采样；没有的话取根音最近的
+pub fn pick(zones: &[Zone], key: i32) -> &Zone {
+    zones
+        .iter()
+        .filter(|z| z.key_lo <= key && key <= z.key_hi)
+        .min_by_key(|z| (z.root_key - key).abs())
+        .unwrap_or_else(|| zones.iter().min_by_key(|z| (z.root_key - key).abs()).unwrap())
+}
+
+/// 用 zone 弹 MIDI 音高为 key 的音，长 len 个采样。有循环点时在循环段里反复，
+/// 所以可以任意长；没有循环点时采样放完就是静音
+pub fn play(zone: &Zone, key: i32, len: usize, rate: u32) -> Vec<f32> {
+    let step = 2f64.powf((key - zone.root_key) as f64 / 12.0 + zone.tune / 12.0) * zone.sample_rate as f64 / rate as f64;
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0.0;
+    for _ in 0..len {
+        if zone.looped && pos >= zone.loop_end as f64 {
+            pos -= (zone.loop_end - zone.loop_start) as f64;
+        }
+        let k = pos as usize;
+        if k >= zone.data.len() {
+            out.push(0.0);
+            pos += step;
+            continue;
+        }
+        let frac = (pos - k as f64) as f32;
+        let a = zone.data[k];
+        let next = if zone.looped && k + 1 == zone.loop_end { zone.loop_start } else { k + 1 };
+        let b = zone.data.get(next).copied().unwrap_or(a);
+        out.push(a + (b - a) * frac);
+        pos += step;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend((body.len() as u32).to_le_bytes());
+        out.extend(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn list(kind: &[u8], subs: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[kind, &subs.concat()].concat())
+    }
+
+    /// 一个 preset（0 号）、一个 instrument 的音色库，instrument 的区域指向第 sample 个采样，
+    /// 采样是 smpl 里 start..end 之间的 8 个点，2..6 是循环段
+    fn sf2(sample: u16, start: u32, end: u32) -> Vec<u8> {
+        let record = |name: &str, fields: &[&[u8]], len: usize| {
+            let mut out = name.as_bytes().to_vec();
+            out.resize(20, 0);
+            fields.iter().for_each(|f| out.extend(*f));
+            out.resize(len, 0);
+            out
+        };
+        let gen = |oper: u16, amount: [u8; 2]| [&oper.to_le_bytes()[..], &amount[..]].concat();
+        let phdr = [
+            record(
+                "lead",
+                &[
+                    &0u16.to_le_bytes(),
+                    &0u16.to_le_bytes(),
+                    &0u16.to_le_bytes(),
+                ],
+                38,
+            ),
+            record(
+                "EOP",
+                &[
+                    &0u16.to_le_bytes(),
+                    &0u16.to_le_bytes(),
+                    &1u16.to_le_bytes(),
+                ],
+                38,
+            ),
+        ]
+        .concat();
+        let inst = [
+            record("square", &[&0u16.to_le_bytes()], 22),
+            record("EOI", &[&2u16.to_le_bytes()], 22),
+        ]
+        .concat();
+        let shdr = [
+            record(
+                "wave",
+                &[
+                    &start.to_le_bytes(),
+                    &end.to_le_bytes(),
+                    &(start + 2).to_le_bytes(),
+                    &(start + 6).to_le_bytes(),
+                    &22050u32.to_le_bytes(),
+                    &[69, 0],
+                ],
+                46,
+            ),
+            record("EOS", &[], 46),
+        ]
+        .concat();
+        let smpl: Vec<u8> = (0..8i16).flat_map(|k| (k * 1024).to_le_bytes()).collect();
+        let pdta = list(
+            b"pdta",
+            &[
+                chunk(b"phdr", &phdr),
+                chunk(b"pbag", &[0, 0, 0, 0, 1, 0, 0, 0]),
+                chunk(b"pgen", &[gen(INSTRUMENT, [0, 0]), gen(0, [0, 0])].concat()),
+                chunk(b"inst", &inst),
+                // 第一个区域没有 sampleID，是全局区域
+                chunk(b"ibag", &[0, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0]),
+                chunk(
+                    b"igen",
+                    &[
+                        gen(SAMPLE_MODES, [1, 0]),
+                        gen(KEY_RANGE, [48, 72]),
+                        gen(SAMPLE_ID, sample.to_le_bytes()),
+                        gen(0, [0, 0]),
+                    ]
+                    .concat(),
+                ),
+                chunk(b"shdr", &shdr),
+            ],
+        );
+        let sdta = list(b"sdta", &[chunk(b"smpl", &smpl)]);
+        chunk(b"RIFF", &[&b"sfbk"[..], &sdta, &pdta].concat())
+    }
+
+    fn load_bytes(name: &str, bytes: &[u8], preset: i32) -> Result<Vec<Zone>> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes).unwrap();
+        let zones = load(path.to_str().unwrap(), preset);
+        let _ = fs::remove_file(&path);
+        zones
+    }
+
+    #[test]
+    fn chunk_walker() {
+        let bytes = [chunk(b"abc ", &[1, 2, 3]), chunk(b"de  ", &[4])].concat();
+        let found = chunks(&bytes, 0, bytes.len());
+        assert_eq!(
+            found,
+            [(&b"abc "[..], &[1, 2, 3][..]), (&b"de  "[..], &[4][..])]
+        );
+        // 声明的长度超过父块时截断到父块结尾
+        let mut bytes = chunk(b"long", &[1, 2]);
+        bytes[4] = 100;
+        assert_eq!(
+            chunks(&bytes, 0, bytes.len()),
+            [(&b"long"[..], &[1, 2][..])]
+        );
+    }
+
+    #[test]
+    fn load_preset() {
+        let zones = load_bytes("sysylive_sf2_ok.sf2", &sf2(0, 0, 8), 0).unwrap();
+        assert_eq!(zones.len(), 1);
+        let zone = &zones[0];
+        assert_eq!((zone.key_lo, zone.key_hi, zone.root_key), (48, 72, 69));
+        assert_eq!(zone.sample_rate, 22050);
+        assert_eq!(zone.data.len(), 8);
+        assert!(zone.looped && (zone.loop_start, zone.loop_end) == (2, 6));
+        // 按根音和采样率原样放出来，放到循环段结尾就回到开头
+        let out = play(pick(&zones, 60), 69, 8, 22050);
+        assert_eq!(out[..6], zone.data[..6]);
+        assert_eq!(out[6..], zone.data[2..4]);
+    }
+
+    #[test]
+    fn bad_headers() {
+        let err = |bytes: &[u8], preset| {
+            load_bytes("sysylive_sf2_bad.sf2", bytes, preset)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            err(b"RIFF\0\0\0\0WAVE", 0),
+            "bad SoundFont: not a RIFF/sfbk file"
+        );
+        assert_eq!(err(&sf2(0, 0, 8), 3), "bad SoundFont: no preset 3");
+        // sampleID 超出 shdr，或者采样的起止不在 smpl 里，都当作没有采样
+        assert_eq!(
+            err(&sf2(5, 0, 8), 0),
+            "bad SoundFont: preset 0 has no samples"
+        );
+        assert_eq!(
+            err(&sf2(0, 100, 108), 0),
+            "bad SoundFont: preset 0 has no samples"
+        );
+        // 结尾超出 smpl 的采样截断到 smpl 结尾
+        let zones = load_bytes("sysylive_sf2_long.sf2", &sf2(0, 4, 100), 0).unwrap();
+        assert_eq!(zones[0].data.len(), 4);
+        let mut bytes = sf2(0, 0, 8);
+        let at = bytes.windows(4).position(|w| w == b"shdr").unwrap();
+        bytes[at..at + 4].copy_from_slice(b"xxxx");
+        assert_eq!(err(&bytes, 0), "bad SoundFont: missing shdr chunk");
+    }
+}
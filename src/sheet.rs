@@ -0,0 +1,252 @@
+// 导出乐谱（MusicXML 等）用的中间形式：把 Score 字面量的 NoteElem 树展开成一个个音，
+// 记下每个音的实际时值、连音（{} 里不是 2 的幂个音）、延音线、力度记号和演奏法。
+
+use crate::ast::*;
+use crate::gen_music::{bar_beats, gcd};
+
+#[derive(Clone)]
+pub struct SheetNote {
+    pub pitches: Vec<(i32, Spelling)>,  // 空的是休止符；和弦有好几个音
+    pub duration: (i32, i32),           // 实际时值，以四分音符为一拍
+    pub tuplet: (i32, i32), // 所在连音的 (实际音数, 正常音数)，嵌套时相乘，不在连音里是 (1, 1)
+    pub tuplet_starts: Vec<(i32, i32)>, // 从这个音开始的连音，外层在前
+    pub tuplet_stops: usize, // 在这个音结束的连音个数
+    pub tie_start: bool,
+    pub tie_stop: bool,
+    pub articulation: i32,
+    pub dynamics: Vec<Dynamic>, // 写在这个音前面的力度记号
+}
+
+impl SheetNote {
+    /// 记谱时值：实际时值乘上连音的比例，例如三连音里的 1/3 拍记作八分音符
+    pub fn written(&self) -> (i32, i32) {
+        let (fz, fm) = (
+            self.duration.0 * self.tuplet.0,
+            self.duration.1 * self.tuplet.1,
+        );
+        let g = gcd(fz, fm);
+        (fz / g, fm / g)
+    }
+}
+
+pub struct SheetBar {
+    pub beats: (i32, i32),
+    pub time: Option<(i32, i32)>, // 从这个小节开始的拍号，原样的分子分母；和前面一样时是 None
+    pub pickup: bool,             // 弱起小节，比拍号短
+    pub notes: Vec<SheetNote>,
+}
+
+/// 拍号 (分子, 分母) 每小节几拍，以四分音符为一拍
+fn meter_beats((num, den): (i32, i32)) -> (i32, i32) {
+    let g = gcd(num * 4, den);
+    (num * 4 / g, den / g)
+}
+
+/// 给每个小节标上拍号。没有声明拍号时按小节的长度推断，3 拍是 3/4，3/2 拍是 3/8；
+/// 比第二小节短的第一小节是弱起，比前一小节短的最后一小节也不换拍号
+fn mark_meters(bars: &mut [SheetBar], meter: Option<(i32, i32)>) {
+    let shorter = |a: (i32, i32), b: (i32, i32)| a.0 * b.1 < b.0 * a.1;
+    let mut current = meter;
+    for i in 0..bars.len() {
+        let beats = bars[i].beats;
+        if beats.0 == 0 {
+            continue;
+        }
+        let last = i + 1 == bars.len();
+        match current {
+            Some(m) if i == 0 => {
+                bars[i].time = Some(m);
+                bars[i].pickup = !last && shorter(beats, meter_beats(m));
+            }
+            Some(m) if meter_beats(m) == beats || (last && shorter(beats, meter_beats(m))) => {}
+            _ => {
+                let next = bars.get(i + 1).map(|b| b.beats).filter(|_| i == 0);
+                let full = match next {
+                    Some(next) if shorter(beats, next) => {
+                        bars[i].pickup = true;
+                        next
+                    }
+                    _ => beats,
+                };
+                current = Some((full.0, full.1 * 4));
+                bars[i].time = current;
+            }
+        }
+    }
+}
+
+/// 程序里所有的 Score 字面量（Score x(syllablename = ...)），按出现顺序
+pub fn score_literals(ast: &CompUnit) -> Vec<(&str, &Score)> {
+    let mut ret = Vec::new();
+    for decl in ast.global_var_defs.iter() {
+        decl_scores(decl, &mut ret);
+    }
+    for func in ast.func_defs.iter() {
+        block_scores(&func.block, &mut ret);
+    }
+    ret
+}
+
+fn decl_scores<'a>(decl: &'a Decl, ret: &mut Vec<(&'a str, &'a Score)>) {
+    for def in decl.defs.iter() {
+        if let VarDef::ScoreInit(id, score) = def.as_ref() {
+            ret.push((id, score));
+        }
+    }
+}
+
+fn block_scores<'a>(block: &'a Block, ret: &mut Vec<(&'a str, &'a Score)>) {
+    for item in block.vecitem.iter() {
+        match item.as_ref() {
+            BlockItem::Decl(decl) => decl_scores(decl, ret),
+            BlockItem::Stmt(stmt) => stmt_scores(stmt, ret),
+        }
+    }
+}
+
+fn stmt_scores<'a>(stmt: &'a Stmt, ret: &mut Vec<(&'a str, &'a Score)>) {
+    match stmt {
+        Stmt::Block(block) => block_scores(block, ret),
+        Stmt::If(_, stmt) | Stmt::While(_, stmt) => stmt_scores(stmt, ret),
+        Stmt::IfElse(_, stmt1, stmt2) => {
+            stmt_scores(stmt1, ret);
+            stmt_scores(stmt2, ret);
+        }
+        _ => {}
+    }
+}
+
+/// 展开过程中的状态：已经展开的音，和还没有落到音上的力度记号
+struct Walker {
+    notes: Vec<SheetNote>,
+    dynamics: Vec<Dynamic>,
+    ties: Vec<bool>, // 和 notes 一一对应，是不是 - 或 _
+}
+
+impl Walker {
+    fn walk(&mut self, elem: &NoteElem, fz: i32, fm: i32, tuplet: (i32, i32), articulation: i32) {
+        match elem {
+            NoteElem::Scaled(elem, sz, sm) => {
+                let g = gcd(fz * sz, fm * sm);
+                self.walk(elem, fz * sz / g, fm * sm / g, tuplet, articulation);
+            }
+            NoteElem::Notes(elems) => {
+                let count = elems
+                    .iter()
+                    .filter(|e| !matches!(***e, NoteElem::Dynamic(..)))
+                    .count() as i32;
+                if count == 0 {
+                    elems
+                        .iter()
+                        .for_each(|e| self.walk(e, fz, fm, tuplet, articulation));
+                    return;
+                }
+                // 2 的幂个音是普通的细分，否则是连音，记成最接近的 2 的幂个音的时值
+                let normal = 1 << (31 - count.leading_zeros());
+                let first = self.notes.len();
+                let inner = if normal == count {
+                    tuplet
+                } else {
+                    let g = gcd(tuplet.0 * count, tuplet.1 * normal);
+                    (tuplet.0 * count / g, tuplet.1 * normal / g)
+                };
+                for elem in elems.iter() {
+                    self.walk(elem, fz, fm * count, inner, articulation);
+                }
+                if normal != count && self.notes.len() > first {
+                    self.notes[first].tuplet_starts.push((count, normal));
+                    self.notes.last_mut().unwrap().tuplet_stops += 1;
+                }
+            }
+            NoteElem::Velocity(elem, _) => self.walk(elem, fz, fm, tuplet, articulation),
+            NoteElem::Articulated(elem, flags) => {
+                self.walk(elem, fz, fm, tuplet, articulation | flags)
+            }
+            NoteElem::Dynamic(dynamic, _) => self.dynamics.push(*dynamic),
+            NoteElem::Tie | NoteElem::Note(_) | NoteElem::Chord(_) => {
+                let notes: &[Note] = match elem {
+                    NoteElem::Note(note) => std::slice::from_ref(note),
+                    NoteElem::Chord(notes) => notes,
+                    _ => &[],
+                };
+                let pitches = notes
+                    .iter()
+                    .filter_map(|note| match note {
+                        Note::Semitone(n, spelling) | Note::Pitch(n, spelling) => {
+                            Some((*n, *spelling))
+                        }
+                        Note::Rest => None,
+                    })
+                    .collect();
+                self.notes.push(SheetNote {
+                    pitches,
+                    duration: (fz, fm),
+                    tuplet,
+                    tuplet_starts: Vec::new(),
+                    tuplet_stops: 0,
+                    tie_start: false,
+                    tie_stop: false,
+                    articulation,
+                    dynamics: std::mem::take(&mut self.dynamics),
+                });
+                self.ties.push(matches!(elem, NoteElem::Tie));
+            }
+        }
+    }
+}
+
+/// 把乐谱展开成一个个小节的音。- 和 _ 换成和前一个音（可以在上一个小节）同音高、用延音线连起来的音
+pub fn sheet_bars(score: &Score) -> Vec<SheetBar> {
+    let mut bars = Vec::new();
+    let mut ties = Vec::new();
+    let mut walker = Walker {
+        notes: Vec::new(),
+        dynamics: Vec::new(),
+        ties: Vec::new(),
+    };
+    for bar in score.bars.iter() {
+        for elem in bar.notes.iter() {
+            walker.walk(elem, 1, 1, (1, 1), 0);
+        }
+        bars.push(SheetBar {
+            beats: bar_beats(bar),
+            time: None,
+            pickup: false,
+            notes: std::mem::take(&mut walker.notes),
+        });
+        ties.push(std::mem::take(&mut walker.ties));
+    }
+    let mut prev: Option<(usize, usize)> = None;
+    for (i, bar_ties) in ties.iter().enumerate() {
+        for (j, &tie) in bar_ties.iter().enumerate() {
+            if tie {
+                if let Some((pi, pj)) = prev {
+                    let pitches = bars[pi].notes[pj].pitches.clone();
+                    if !pitches.is_empty() {
+                        bars[pi].notes[pj].tie_start = true;
+                        bars[i].notes[j].tie_stop = true;
+                    }
+                    bars[i].notes[j].pitches = pitches;
+                }
+            }
+            prev = Some((i, j));
+        }
+    }
+    mark_meters(&mut bars, score.meter);
+    bars
+}
+
+/// 记谱时值 (fz, fm) 拍对应的音符：(几分音符, 附点个数)，全音符是 1。写不成一个音符时返回 None
+pub fn note_value(written: (i32, i32)) -> Option<(i32, usize)> {
+    let (fz, fm) = written;
+    for dots in 0..4 {
+        // 音符本身的时值 = 总时值 * 2^dots / (2^(dots+1) - 1)，base 拍的音符就是 4 / base 分音符
+        let (bz, bm) = (fz << dots, fm * ((2 << dots) - 1));
+        let g = gcd(bz, bm);
+        let (bz, bm) = (bz / g, bm / g);
+        if 4 * bm % bz == 0 && (4 * bm / bz).count_ones() == 1 && 4 * bm / bz <= 64 {
+            return Some((4 * bm / bz, dots));
+        }
+    }
+    None
+}
@@ -1,14 +1,32 @@
 use crate::arrayinit::*;
 use crate::ast::*;
 use crate::constint::*;
+use crate::diagnostic::*;
 use crate::gen_music::*;
 use crate::ident::*;
+use crate::libfuncs::*;
 use crate::whilecontext::*;
 use koopa::ir::builder::LocalInstBuilder;
 use koopa::ir::builder_traits::*;
 use koopa::ir::*;
 use std::collections::HashMap;
 
+fn lib_type(typ: LibType) -> Type {
+    match typ {
+        LibType::Unit => Type::get_unit(),
+        LibType::Int => Type::get_i32(),
+        LibType::IntPtr => Type::get_pointer(Type::get_i32()),
+    }
+}
+
+/// 构造全局音乐对象的函数，由 main 在最开始调用
+const MUSIC_INIT: &str = "__sysylive_init";
+
+/// 字符串字面量在 var 里的名字，带着引号，不会和标识符重名
+fn string_key(s: &str) -> String {
+    format!("\"{}\"", s)
+}
+
 impl CompUnit {
     fn adddecl(
         &self,
@@ -37,216 +55,28 @@ impl CompUnit {
     decl @stoptime()
     */
     fn gen_libfuncs(&self, program: &mut Program, var: &mut HashMap<String, IdentValue>) {
-        self.adddecl(program, var, "getint".into(), Type::get_i32(), Vec::new());
-        self.adddecl(program, var, "getch".into(), Type::get_i32(), Vec::new());
-        self.adddecl(
-            program,
-            var,
-            "getarray".into(),
-            Type::get_i32(),
-            vec![Type::get_pointer(Type::get_i32())],
-        );
-        self.adddecl(
-            program,
-            var,
-            "putint".into(),
-            Type::get_unit(),
-            vec![Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "putch".into(),
-            Type::get_unit(),
-            vec![Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "putarray".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_pointer(Type::get_i32())],
-        );
-        self.adddecl(
-            program,
-            var,
-            "starttime".into(),
-            Type::get_unit(),
-            Vec::new(),
-        );
-        self.adddecl(
-            program,
-            var,
-            "stoptime".into(),
-            Type::get_unit(),
-            Vec::new(),
-        );
+        // 签名统一写在 libfuncs.rs 里，运行时和头文件都以它为准
+        for func in LIBFUNCS.iter() {
+            self.adddecl(
+                program,
+                var,
+                func.name.into(),
+                lib_type(func.ret),
+                func.params.iter().map(|p| lib_type(*p)).collect(),
+            );
+        }
+    }
 
-        // now comes the music decls
-        self.adddecl(
-            program,
-            var,
-            "track_copy".into(),
-            Type::get_unit(),
-            vec![
-                Type::get_i32(),
-                Type::get_pointer(Type::get_i32()),
-                Type::get_pointer(Type::get_i32()),
-            ],
-        );
-        self.adddecl(
-            program,
-            var,
-            "track_load".into(),
-            Type::get_unit(),
-            vec![
-                Type::get_i32(),
-                Type::get_pointer(Type::get_i32()),
-            ],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_sing".into(),
-            Type::get_unit(),
-            vec![
-                Type::get_i32(),
-                Type::get_pointer(Type::get_i32()),
-                Type::get_pointer(Type::get_i32()),
-                Type::get_i32(),
-                Type::get_i32(),
-                Type::get_i32(),
-            ],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_push".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "bar_push".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "newnote".into(),
-            Type::get_i32(),
-            vec![Type::get_i32(), Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "newnote_rest".into(),
-            Type::get_i32(),
-            vec![Type::get_i32(), Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(program, var, "newbar".into(), Type::get_i32(), vec![]);
-        self.adddecl(program, var, "newscore".into(), Type::get_i32(), vec![]);
-        self.adddecl(program, var, "newtrack".into(), Type::get_i32(), vec![]);
-        self.adddecl(
-            program,
-            var,
-            "bar_setbpm".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_setbpm".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_append".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "bar_copy".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_copy".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_replace".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_inc_pitch".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "bar_inc_pitch".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "score_set_duration".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "bar_set_duration".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "track_append".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "track_stack".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "track_set_volume".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32(), Type::get_i32()],
-        );
-        self.adddecl(
-            program,
-            var,
-            "track_append_silence".into(),
-            Type::get_unit(),
-            vec![Type::get_i32(), Type::get_i32(), Type::get_i32(), Type::get_i32(), Type::get_i32()],
-        );
+    // 每个字符串字面量对应一个以 0 结尾的全局 int 数组，一个字符占一个 int
+    fn gen_strings(&self, program: &mut Program, var: &mut HashMap<String, IdentValue>) {
+        for s in self.strings.iter() {
+            let mut all: Vec<i32> = s.chars().map(|c| c as i32).collect();
+            all.push(0);
+            let len = vec![all.len() as i32];
+            let initv = gen_globalinitvalue(program, len, all);
+            let alloc = program.new_value().global_alloc(initv);
+            var.insert(string_key(s), IdentValue::Array(alloc, 1));
+        }
     }
 
     pub fn gen_ir(&self) -> Program {
@@ -255,6 +85,8 @@ impl CompUnit {
         let mut tmpmap: HashMap<String, i32> = HashMap::new();
         let mut initmap: HashMap<String, Vec<i32>> = HashMap::new();
         let mut sizemap: HashMap<String, Vec<i32>> = HashMap::new();
+        let mut musicglobals: Vec<(&VarDef, Value)> = Vec::new();
+        self.gen_strings(&mut program, &mut var);
         {
             // 只能定义一个伪函数
             let pseudo = program.new_func(FunctionData::new(
@@ -276,7 +108,11 @@ impl CompUnit {
                             if let Some(res) = get_const_int(pseudo_data, val) {
                                 tmpmap.insert(id.clone(), res);
                             } else {
-                                panic!("global initial value not a constant");
+                                report(Diagnostic::error(
+                                    exp.span,
+                                    format!("initial value of global `{}` is not a constant", id),
+                                ));
+                                tmpmap.insert(id.clone(), 0);
                             }
                         }
                         VarDef::ConstIdentInit(id, exp) => {
@@ -285,34 +121,22 @@ impl CompUnit {
                                 tmpmap.insert(id.clone(), res.clone());
                                 var.insert(id.clone(), IdentValue::ConstValue(res));
                             } else {
-                                panic!("global initial value not a constant");
+                                report(Diagnostic::error(
+                                    exp.span,
+                                    format!("initial value of const `{}` is not a constant", id),
+                                ));
+                                var.insert(id.clone(), IdentValue::ConstValue(0));
                             }
                         }
                         // 对于数组，init value 和 size 都是常数，都需要借助 pseudo 函数来计算
                         // size 用 sizemap 寸
                         // initvalue 用 initmap 存
                         VarDef::Array(id, exps) => {
-                            let mut lens: Vec<i32> = Vec::new();
-                            for exp in exps.iter() {
-                                let val = exp.gen_ir(pseudo_data, &mut entry, &mut var);
-                                if let Some(res) = get_const_int(pseudo_data, val) {
-                                    lens.push(res);
-                                } else {
-                                    panic!("global array size not a constant");
-                                }
-                            }
+                            let lens = gen_arraylens(pseudo_data, &mut entry, &mut var, id, exps);
                             sizemap.insert(id.clone(), lens.clone());
                         }
                         VarDef::ArrayInit(id, exps, arrayinit) => {
-                            let mut lens: Vec<i32> = Vec::new();
-                            for exp in exps.iter() {
-                                let val = exp.gen_ir(pseudo_data, &mut entry, &mut var);
-                                if let Some(res) = get_const_int(pseudo_data, val) {
-                                    lens.push(res);
-                                } else {
-                                    panic!("global array size not a constant");
-                                }
-                            }
+                            let lens = gen_arraylens(pseudo_data, &mut entry, &mut var, id, exps);
                             sizemap.insert(id.clone(), lens.clone());
                             let res = gen_globalarrayinit(
                                 pseudo_data,
@@ -323,7 +147,7 @@ impl CompUnit {
                             );
                             initmap.insert(id.clone(), res);
                         }
-                        _ => panic!("no global music declarations is allowed"),
+                        _ => {} // 音乐对象要在运行时构造，见下面的 MUSIC_INIT
                     }
                 }
             }
@@ -331,17 +155,10 @@ impl CompUnit {
             for func in self.func_defs.iter() {
                 for param in func.params.iter() {
                     match param {
-                        FuncParam::Var(_) => {}
+                        FuncParam::Var(_) | FuncParam::Music(_, _) => {}
                         FuncParam::Array(paramid, exps) => {
-                            let mut lens: Vec<i32> = Vec::new();
-                            for exp in exps.iter() {
-                                let val = exp.gen_ir(pseudo_data, &mut entry, &mut var);
-                                if let Some(res) = get_const_int(pseudo_data, val) {
-                                    lens.push(res);
-                                } else {
-                                    panic!("global array size not a constant");
-                                }
-                            }
+                            let lens =
+                                gen_arraylens(pseudo_data, &mut entry, &mut var, paramid, exps);
                             let newparamid = func.id.clone() + "!" + paramid;
                             sizemap.insert(newparamid.clone(), lens.clone());
                         }
@@ -392,7 +209,24 @@ impl CompUnit {
                             let alloc = program.new_value().global_alloc(initv);
                             var.insert(id.clone(), IdentValue::Array(alloc, len.len() as i32));
                         }
-                        _ => unreachable!(),
+                        VarDef::ScoreInit(id, _)
+                        | VarDef::BarInit(id, _)
+                        | VarDef::NoteInit(id, _, _, _)
+                        | VarDef::BarCopy(id, _)
+                        | VarDef::Bar(id)
+                        | VarDef::Score(id)
+                        | VarDef::ScoreCopy(id, _)
+                        | VarDef::Track(id)
+                        | VarDef::TrackLoad(id, _)
+                        | VarDef::TrackCopy(id, _, _)
+                        | VarDef::Instrument(id)
+                        | VarDef::MusicBind(_, id, _) => {
+                            // 先占一个全局的句柄，真正的对象在 MUSIC_INIT 里构造
+                            let zero = program.new_value().integer(0);
+                            let alloc = program.new_value().global_alloc(zero);
+                            var.insert(id.clone(), IdentValue::Value(alloc));
+                            musicglobals.push((vardef.as_ref(), alloc));
+                        }
                     }
                 }
             }
@@ -402,13 +236,16 @@ impl CompUnit {
 
         self.gen_libfuncs(&mut program, &mut var);
 
+        // 构造全局音乐对象的函数紧挨着放在 main 前面：
+        // 它要调用的函数得在它前面定义（LLVM 后端的要求），main 调用它也一样
+        let mut init = None;
         for func in self.func_defs.iter() {
             let mut typevec = Vec::new();
 
             for param in func.params.iter() {
                 // typevec.push(Type::get_i32()); // 目前只有 int 一个类型
                 match param {
-                    FuncParam::Var(_) => {
+                    FuncParam::Var(_) | FuncParam::Music(_, _) => {
                         typevec.push(Type::get_i32());
                     }
                     FuncParam::Array(paramid, _) => {
@@ -423,17 +260,49 @@ impl CompUnit {
                 }
             }
 
+            if func.id == "main" && !musicglobals.is_empty() {
+                let initfunc = program.new_func(FunctionData::new(
+                    format!("@{}", MUSIC_INIT),
+                    Vec::new(),
+                    Type::get_unit(),
+                ));
+                var.insert(MUSIC_INIT.into(), IdentValue::Func(initfunc));
+                init = Some(initfunc);
+            }
+
             let main = program.new_func(FunctionData::new(
                 ("@".to_owned() + &func.id).into(),
                 typevec,
                 match func.func_type {
-                    FuncType::Int => Type::get_i32(),
+                    FuncType::Int | FuncType::Music(_) => Type::get_i32(),
                     FuncType::Void => Type::get_unit(),
                 },
             ));
             var.insert(func.id.clone(), IdentValue::Func(main));
         }
 
+        if let Some(init) = init {
+            let init_data = program.func_mut(init);
+            let mut entry = init_data.dfg_mut().new_bb().basic_block(None);
+            let _ = init_data.layout_mut().bbs_mut().push_key_back(entry);
+            for (vardef, alloc) in musicglobals.iter() {
+                if let Some((_, it)) = vardef.gen_music_handle(init_data, &mut entry, &mut var) {
+                    let store = init_data.dfg_mut().new_value().store(it, *alloc);
+                    init_data
+                        .layout_mut()
+                        .bb_mut(entry)
+                        .insts_mut()
+                        .extend([store]);
+                }
+            }
+            let ret = init_data.dfg_mut().new_value().ret(None);
+            init_data
+                .layout_mut()
+                .bb_mut(entry)
+                .insts_mut()
+                .extend([ret]);
+        }
+
         for func in self.func_defs.iter() {
             let main = var.get(&func.id).unwrap().clone();
             if let IdentValue::Func(main) = main {
@@ -455,9 +324,24 @@ impl FuncDef {
 
         let mut myvar = var.clone();
 
+        // main 一开始先把全局的音乐对象构造好
+        if self.id == "main" {
+            if let Some(IdentValue::Func(init)) = var.get(MUSIC_INIT) {
+                let call = main_data.dfg_mut().new_value().call(*init, Vec::new());
+                main_data
+                    .layout_mut()
+                    .bb_mut(entry)
+                    .insts_mut()
+                    .extend([call]);
+            }
+        }
+
         let mut id = 0;
         for param in self.params.iter() {
-            let funcparamval = main_data.params()[id];
+            let mut funcparamval = main_data.params()[id];
+            if let FuncParam::Music(ty, _) = param {
+                funcparamval = gen_music_copy(main_data, &mut entry, &mut myvar, *ty, funcparamval);
+            }
             let paramtyp = main_data.dfg().value(funcparamval).ty().clone();
             let alloc = main_data.dfg_mut().new_value().alloc(paramtyp);
             let store = main_data.dfg_mut().new_value().store(funcparamval, alloc);
@@ -467,7 +351,7 @@ impl FuncDef {
                 .insts_mut()
                 .extend([alloc, store]);
             match param {
-                FuncParam::Var(paramid) => {
+                FuncParam::Var(paramid) | FuncParam::Music(_, paramid) => {
                     myvar.insert(paramid.clone(), IdentValue::Value(alloc));
                 }
                 FuncParam::Array(paramid, exps) => {
@@ -485,7 +369,7 @@ impl FuncDef {
         // add an unreachable "return" at the end
         let zero = main_data.dfg_mut().new_value().integer(0);
         let ret = main_data.dfg_mut().new_value().ret(match self.func_type {
-            FuncType::Int => Some(zero),
+            FuncType::Int | FuncType::Music(_) => Some(zero),
             FuncType::Void => None,
         });
         main_data
@@ -506,10 +390,21 @@ impl ArrayInit {
         lens: Vec<i32>,
     ) {
         let mut curpos = 0;
+        let all = lens.iter().fold(1, |acc, x| acc * x);
         match self {
-            ArrayInit::Single(_) => panic!("using an exp to init an array"),
-            ArrayInit::Multiple(inits) => {
+            ArrayInit::Single(exp) => report(Diagnostic::error(
+                exp.span,
+                "array must be initialized with `{ ... }`",
+            )),
+            ArrayInit::Multiple(inits, span) => {
                 for cur in inits.iter() {
+                    if curpos >= all {
+                        report(Diagnostic::error(
+                            *span,
+                            "too many elements in array initializer",
+                        ));
+                        break;
+                    }
                     match cur.as_ref() {
                         ArrayInit::Single(exp) => {
                             let val = exp.gen_ir(data, entry, var);
@@ -518,9 +413,10 @@ impl ArrayInit {
                             data.layout_mut().bb_mut(*entry).insts_mut().extend([store]);
                             curpos += 1;
                         }
-                        ArrayInit::Multiple(_) => {
-                            if curpos % lens.last().unwrap() != 0 {
-                                panic!("bad array initializer");
+                        ArrayInit::Multiple(_, subspan) => {
+                            if lens.is_empty() || curpos % lens.last().unwrap() != 0 {
+                                report(Diagnostic::error(*subspan, "bad array initializer"));
+                                break;
                             }
                             let respr = find_firstok(curpos, lens.clone());
                             let firstok: i32 = std::cmp::max(respr.0, 1);
@@ -547,7 +443,6 @@ impl ArrayInit {
                         }
                     }
                 }
-                let all = lens.iter().fold(1, |acc, x| acc * x);
                 while curpos < all {
                     let val = data.dfg_mut().new_value().integer(0);
                     let at = gen_arrayelem_ptr(data, entry, alloc, curpos, lens.clone());
@@ -583,152 +478,89 @@ impl VarDef {
             }
             VarDef::ConstIdentInit(id, exp) => {
                 let val = exp.gen_ir(data, entry, var);
-                assert!(data.dfg_mut().value(val).kind().is_const());
-                var.insert(
-                    id.clone(),
-                    IdentValue::ConstValue(get_const_int(data, val).unwrap().clone()),
-                ); // const 变量直接存值
+                let res = get_const_int(data, val).unwrap_or_else(|| {
+                    report(Diagnostic::error(
+                        exp.span,
+                        format!("initial value of const `{}` is not a constant", id),
+                    ));
+                    0
+                });
+                var.insert(id.clone(), IdentValue::ConstValue(res)); // const 变量直接存值
             }
             VarDef::Array(id, exps) => {
-                let mut lens: Vec<i32> = Vec::new();
-                for exp in exps.iter() {
-                    let val = exp.gen_ir(data, entry, var);
-                    if let Some(rv) = get_const_int(data, val) {
-                        lens.push(rv);
-                    } else {
-                        panic!("array size of array {} is not a constant", id);
-                    }
-                }
+                let lens = gen_arraylens(data, entry, var, id, exps);
                 let curtype = gen_arraytype(lens);
                 let alloc = data.dfg_mut().new_value().alloc(curtype);
                 data.layout_mut().bb_mut(*entry).insts_mut().extend([alloc]);
                 var.insert(id.clone(), IdentValue::Array(alloc, exps.len() as i32));
             }
             VarDef::ArrayInit(id, exps, arrayinit) => {
-                let mut lens: Vec<i32> = Vec::new();
-                for exp in exps.iter() {
-                    let val = exp.gen_ir(data, entry, var);
-                    if let Some(rv) = get_const_int(data, val) {
-                        lens.push(rv);
-                    } else {
-                        panic!("array size of array {} is not a constant", id);
-                    }
-                }
+                let lens = gen_arraylens(data, entry, var, id, exps);
                 let curtype = gen_arraytype(lens.clone());
                 let alloc = data.dfg_mut().new_value().alloc(curtype);
                 data.layout_mut().bb_mut(*entry).insts_mut().extend([alloc]);
                 var.insert(id.clone(), IdentValue::Array(alloc, exps.len() as i32));
                 arrayinit.gen_ir(data, entry, var, alloc, lens.clone());
             }
-            VarDef::ScoreInit(id, score) => {
-                let it = gen_score(data, entry, var, score);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(it, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
-            }
-            VarDef::BarInit(id, bar) => {
-                let it = gen_bar(data, entry, var, bar);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(it, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
+            _ => {
+                if let Some((id, it)) = self.gen_music_handle(data, entry, var) {
+                    let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
+                    let store = data.dfg_mut().new_value().store(it, alloc);
+                    data.layout_mut()
+                        .bb_mut(*entry)
+                        .insts_mut()
+                        .extend([alloc, store]);
+                    var.insert(id.clone(), IdentValue::Value(alloc));
+                }
             }
+        }
+    }
+
+    /// 生成音乐对象的构造代码，返回变量名和新对象的句柄；不是音乐对象就返回 None
+    fn gen_music_handle(
+        &self,
+        data: &mut FunctionData,
+        entry: &mut BasicBlock,
+        var: &mut HashMap<String, IdentValue>,
+    ) -> Option<(&String, Value)> {
+        match self {
+            VarDef::ScoreInit(id, score) => Some((id, gen_score(data, entry, var, score))),
+            VarDef::BarInit(id, bar) => Some((id, gen_bar(data, entry, var, bar))),
             VarDef::NoteInit(id, note, fz, fm) => {
-                let it = gen_note(data, entry, var, (**note).clone(), fz.clone(), fm.clone());
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(it, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
+                let it = gen_note(data, entry, var, (**note).clone(), *fz, *fm, DEFAULT_VELOCITY);
+                Some((id, it))
             }
             VarDef::ScoreCopy(id, _y) => {
                 let myid = call_func("newscore", data, entry, var, vec![]);
                 let y = _y.gen_ir(data, entry, var);
                 let _ = call_func("score_copy", data, entry, var, vec![myid, y]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
+                Some((id, myid))
             }
             VarDef::BarCopy(id, _y) => {
                 let myid = call_func("newbar", data, entry, var, vec![]);
                 let y = _y.gen_ir(data, entry, var);
                 let _ = call_func("bar_copy", data, entry, var, vec![myid, y]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
-            }
-            VarDef::Bar(id) => {
-                let myid = call_func("newbar", data, entry, var, vec![]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
-            }
-            VarDef::Score(id) => {
-                let myid = call_func("newscore", data, entry, var, vec![]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
-            }
-            VarDef::Track(id) => {
-                let myid = call_func("newtrack", data, entry, var, vec![]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
+                Some((id, myid))
             }
+            VarDef::Bar(id) => Some((id, call_func("newbar", data, entry, var, vec![]))),
+            VarDef::Score(id) => Some((id, call_func("newscore", data, entry, var, vec![]))),
+            VarDef::Track(id) => Some((id, call_func("newtrack", data, entry, var, vec![]))),
+            VarDef::Instrument(id) => Some((id, call_func("newinstrument", data, entry, var, vec![]))),
             VarDef::TrackLoad(id, _y) => {
                 let myid = call_func("newtrack", data, entry, var, vec![]);
                 let y = _y.gen_ir(data, entry, var);
                 let _ = call_func("track_load", data, entry, var, vec![myid, y]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
+                Some((id, myid))
             }
             VarDef::TrackCopy(id, _y, _z) => {
                 let myid = call_func("newtrack", data, entry, var, vec![]);
                 let y = _y.gen_ir(data, entry, var);
                 let z = _z.gen_ir(data, entry, var);
                 let _ = call_func("track_copy", data, entry, var, vec![myid, y, z]);
-                let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
-                let store = data.dfg_mut().new_value().store(myid, alloc);
-                data.layout_mut()
-                    .bb_mut(*entry)
-                    .insts_mut()
-                    .extend([alloc, store]);
-                var.insert(id.clone(), IdentValue::Value(alloc));
+                Some((id, myid))
             }
+            VarDef::MusicBind(_, id, exp) => Some((id, exp.gen_ir(data, entry, var))),
+            _ => None,
         }
     }
 }
@@ -785,7 +617,7 @@ impl Stmt {
         lastwhile: Option<WhileContext>,
     ) {
         match self {
-            Stmt::Break => {
+            Stmt::Break(span) => {
                 if let Some(context) = lastwhile {
                     let jump = data.dfg_mut().new_value().jump(context.while_end);
                     data.layout_mut().bb_mut(*entry).insts_mut().extend([jump]);
@@ -793,10 +625,10 @@ impl Stmt {
                     *entry = data.dfg_mut().new_bb().basic_block(None);
                     let _ = data.layout_mut().bbs_mut().push_key_back(*entry);
                 } else {
-                    panic!("Break statement outside of loop");
+                    report(Diagnostic::error(*span, "`break` outside of a loop"));
                 }
             }
-            Stmt::Continue => {
+            Stmt::Continue(span) => {
                 if let Some(context) = lastwhile {
                     let jump = data.dfg_mut().new_value().jump(context.while_cond);
                     data.layout_mut().bb_mut(*entry).insts_mut().extend([jump]);
@@ -804,7 +636,7 @@ impl Stmt {
                     *entry = data.dfg_mut().new_bb().basic_block(None);
                     let _ = data.layout_mut().bbs_mut().push_key_back(*entry);
                 } else {
-                    panic!("Continue statement outside of loop");
+                    report(Diagnostic::error(*span, "`continue` outside of a loop"));
                 }
             }
             Stmt::While(exp, stmt) => {
@@ -859,7 +691,7 @@ impl Stmt {
                 //     panic!("trying to assign a function or a const var {}", id);
                 // }
             }
-            Stmt::Return(optexp) => {
+            Stmt::Return(optexp, _) => {
                 if let Some(exp) = (*optexp).as_ref() {
                     let val = exp.gen_ir(data, entry, var);
                     let ret = data.dfg_mut().new_value().ret(Some(val));
@@ -918,14 +750,20 @@ impl Stmt {
                 // if 结束
                 *entry = bb3;
             }
-            Stmt::Sing(_id, _name1, _name2, _name3, _name4, _name5) => {
+            Stmt::Sing(_id, _name1, _name2, _name3, _name4, _name5)
+            | Stmt::SingInstrument(_id, _name1, _name2, _name3, _name4, _name5) => {
                 let id = _id.gen_ir(data, entry, var);
                 let name1 = _name1.gen_ir(data, entry, var);
                 let name2 = _name2.gen_ir(data, entry, var);
                 let name3 = _name3.gen_ir(data, entry, var);
                 let name4 = _name4.gen_ir(data, entry, var);
                 let name5 = _name5.gen_ir(data, entry, var);
-                let func = var.get("score_sing".into()).unwrap().clone();
+                // 音源是 Instrument 时传的是句柄，不是文件名数组
+                let func = if let Stmt::SingInstrument(..) = self {
+                    var.get("score_sing_instrument").unwrap().clone()
+                } else {
+                    var.get("score_sing".into()).unwrap().clone()
+                };
                 if let IdentValue::Func(func) = func {
                     let call = data
                         .dfg_mut()
@@ -936,6 +774,25 @@ impl Stmt {
                     panic!("score_sing is not a function");
                 }
             }
+            Stmt::SingPreset(_id, _name, _preset, _toname, _srate, _bytes, _channels) => {
+                let id = _id.gen_ir(data, entry, var);
+                let name = _name.gen_ir(data, entry, var);
+                let preset = _preset.gen_ir(data, entry, var);
+                let toname = _toname.gen_ir(data, entry, var);
+                let srate = _srate.gen_ir(data, entry, var);
+                let bytes = _bytes.gen_ir(data, entry, var);
+                let channels = _channels.gen_ir(data, entry, var);
+                let func = var.get("score_sing_preset").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data
+                        .dfg_mut()
+                        .new_value()
+                        .call(func, vec![id, name, preset, toname, srate, bytes, channels]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("score_sing_preset is not a function");
+                }
+            }
             Stmt::PushBar(_id, _num) => {
                 let id = _id.gen_ir(data, entry, var);
                 let num = _num.gen_ir(data, entry, var);
@@ -1036,6 +893,43 @@ impl Stmt {
                     panic!("score_set_duration is not a function");
                 }
             }
+            Stmt::SetEnvelope(_id, _a, _d, _s, _r) => {
+                let id = _id.gen_ir(data, entry, var);
+                let a = _a.gen_ir(data, entry, var);
+                let d = _d.gen_ir(data, entry, var);
+                let s = _s.gen_ir(data, entry, var);
+                let r = _r.gen_ir(data, entry, var);
+                let func = var.get("score_set_envelope").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data.dfg_mut().new_value().call(func, vec![id, a, d, s, r]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("score_set_envelope is not a function");
+                }
+            }
+            Stmt::ExportMidi(_id, _name) => {
+                let id = _id.gen_ir(data, entry, var);
+                let name = _name.gen_ir(data, entry, var);
+                let func = var.get("score_export_midi").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data.dfg_mut().new_value().call(func, vec![id, name]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("score_export_midi is not a function");
+                }
+            }
+            Stmt::AddSample(_id, _name, _offset) => {
+                let id = _id.gen_ir(data, entry, var);
+                let name = _name.gen_ir(data, entry, var);
+                let offset = _offset.gen_ir(data, entry, var);
+                let func = var.get("instrument_add_sample").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data.dfg_mut().new_value().call(func, vec![id, name, offset]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("instrument_add_sample is not a function");
+                }
+            }
             Stmt::SetBarDuration(_id, _num) => {
                 let id = _id.gen_ir(data, entry, var);
                 let num = _num.gen_ir(data, entry, var);
@@ -1069,6 +963,40 @@ impl Stmt {
                     panic!("track_stack is not a function");
                 }
             }
+            Stmt::StackTrackAt(_x, _y, _offset) => {
+                let x = _x.gen_ir(data, entry, var);
+                let y = _y.gen_ir(data, entry, var);
+                let offset = _offset.gen_ir(data, entry, var);
+                let func = var.get("track_stack_at").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data.dfg_mut().new_value().call(func, vec![x, y, offset]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("track_stack_at is not a function");
+                }
+            }
+            Stmt::Pan(_id, _num) => {
+                let id = _id.gen_ir(data, entry, var);
+                let num = _num.gen_ir(data, entry, var);
+                let func = var.get("track_pan").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data.dfg_mut().new_value().call(func, vec![id, num]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("track_pan is not a function");
+                }
+            }
+            Stmt::GainDb(_id, _num) => {
+                let id = _id.gen_ir(data, entry, var);
+                let num = _num.gen_ir(data, entry, var);
+                let func = var.get("track_gain_db").unwrap().clone();
+                if let IdentValue::Func(func) = func {
+                    let call = data.dfg_mut().new_value().call(func, vec![id, num]);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
+                } else {
+                    panic!("track_gain_db is not a function");
+                }
+            }
             Stmt::SetVol(_x, _y, _z) => {
                 let x = _x.gen_ir(data, entry, var);
                 let y = _y.gen_ir(data, entry, var);
@@ -1110,6 +1038,16 @@ impl Exp {
     }
 }
 
+/// 出错之后用来代替的值：右值是 0，左值是一个新的临时变量
+fn error_value(data: &mut FunctionData, entry: &mut BasicBlock, needload: bool) -> Value {
+    if needload {
+        return data.dfg_mut().new_value().integer(0);
+    }
+    let alloc = data.dfg_mut().new_value().alloc(Type::get_i32());
+    data.layout_mut().bb_mut(*entry).insts_mut().extend([alloc]);
+    alloc
+}
+
 impl LVal {
     fn gen_ir(
         &self,
@@ -1120,10 +1058,15 @@ impl LVal {
     ) -> Value {
         // 左值不需要 needload，右值就需要 load
         match self {
-            LVal::Ident(id) => {
+            LVal::Ident(id, span) => {
                 if let Some(val) = var.get(id).cloned() {
                     match val {
-                        IdentValue::Func(_) => panic!("Function {} used as variable", id),
+                        IdentValue::Func(_) => {
+                            report(Diagnostic::error(
+                                *span,
+                                format!("function `{}` used as a variable", id),
+                            ));
+                        }
                         IdentValue::Value(val) => {
                             if needload {
                                 let load = data.dfg_mut().new_value().load(val);
@@ -1135,7 +1078,11 @@ impl LVal {
                         }
                         IdentValue::FuncArgumentArray(val, _) => {
                             if !needload {
-                                panic!("Array {} used as left value", id);
+                                report(Diagnostic::error(
+                                    *span,
+                                    format!("cannot assign to array `{}`", id),
+                                ));
+                                return error_value(data, entry, needload);
                             }
                             // 这里的 val 就是和 func 的 val 一样的传法
                             let load = data.dfg_mut().new_value().load(val);
@@ -1144,7 +1091,11 @@ impl LVal {
                         }
                         IdentValue::Array(val, _) => {
                             if !needload {
-                                panic!("Array {} used as left value", id);
+                                report(Diagnostic::error(
+                                    *span,
+                                    format!("cannot assign to array `{}`", id),
+                                ));
+                                return error_value(data, entry, needload);
                             }
                             let zero = data.dfg_mut().new_value().integer(0);
                             let load = data.dfg_mut().new_value().get_elem_ptr(val, zero);
@@ -1152,19 +1103,39 @@ impl LVal {
                             return load;
                         }
                         IdentValue::ConstValue(val) => {
+                            if !needload {
+                                report(Diagnostic::error(
+                                    *span,
+                                    format!("cannot assign to const `{}`", id),
+                                ));
+                                return error_value(data, entry, needload);
+                            }
                             let nval = data.dfg_mut().new_value().integer(val);
                             return nval;
                         }
                     }
                 } else {
-                    panic!("Variable {} not found", id);
+                    report(Diagnostic::error(
+                        *span,
+                        format!("cannot find variable `{}` in this scope", id),
+                    ));
                 }
             }
-            LVal::Array(id, exps) => {
+            LVal::Array(id, exps, span) => {
                 if let Some(val) = var.get(id).cloned() {
                     match val {
-                        IdentValue::Func(_) => panic!("Function {} used as array", id),
-                        IdentValue::Value(_) => panic!("Variable {} used as array", id),
+                        IdentValue::Func(_) => {
+                            report(Diagnostic::error(
+                                *span,
+                                format!("function `{}` used as an array", id),
+                            ));
+                        }
+                        IdentValue::Value(_) => {
+                            report(Diagnostic::error(
+                                *span,
+                                format!("variable `{}` is not an array", id),
+                            ));
+                        }
                         IdentValue::FuncArgumentArray(val0, dim) => {
                             let load0 = data.dfg_mut().new_value().load(val0);
                             data.layout_mut().bb_mut(*entry).insts_mut().extend([load0]);
@@ -1198,7 +1169,11 @@ impl LVal {
                                 return load;
                             } else {
                                 if exps.len() as i32 != dim {
-                                    panic!("Array pointer {} used as left value", id);
+                                    report(Diagnostic::error(
+                                        *span,
+                                        format!("cannot assign to sub-array of `{}`", id),
+                                    ));
+                                    return error_value(data, entry, needload);
                                 }
                                 return ptrval;
                             }
@@ -1227,20 +1202,32 @@ impl LVal {
                                 return load;
                             } else {
                                 if exps.len() as i32 != dim {
-                                    panic!("Array pointer {} used as left value", id);
+                                    report(Diagnostic::error(
+                                        *span,
+                                        format!("cannot assign to sub-array of `{}`", id),
+                                    ));
+                                    return error_value(data, entry, needload);
                                 }
                                 return ptrval;
                             }
                         }
                         IdentValue::ConstValue(_) => {
-                            panic!("Const Variable {} is not array", id);
+                            report(Diagnostic::error(
+                                *span,
+                                format!("const `{}` is not an array", id),
+                            ));
                         }
                     }
                 } else {
-                    panic!("Variable {} not found", id);
+                    report(Diagnostic::error(
+                        *span,
+                        format!("cannot find variable `{}` in this scope", id),
+                    ));
                 }
             }
         }
+        // 出错了，上面已经报过
+        error_value(data, entry, needload)
     }
 }
 
@@ -1261,6 +1248,16 @@ impl PrimaryExp {
                 let val = lval.gen_ir(data, entry, var, true);
                 val
             }
+            PrimaryExp::Str(s, _) => {
+                // 和数组名一样，取首元素的地址
+                if let Some(IdentValue::Array(val, _)) = var.get(&string_key(s)).cloned() {
+                    let zero = data.dfg_mut().new_value().integer(0);
+                    let ptr = data.dfg_mut().new_value().get_elem_ptr(val, zero);
+                    data.layout_mut().bb_mut(*entry).insts_mut().extend([ptr]);
+                    return ptr;
+                }
+                unreachable!("string literal {:?} was not collected", s);
+            }
         }
     }
 }
@@ -1278,7 +1275,7 @@ impl UnaryExp {
             UnaryExp::Neg(unary_exp) => {
                 let val = unary_exp.gen_ir(data, entry, var);
                 if let Some(rv) = get_const_int(data, val) {
-                    return data.dfg_mut().new_value().integer(-rv);
+                    return data.dfg_mut().new_value().integer(rv.wrapping_neg());
                 }
                 let zero = data.dfg_mut().new_value().integer(0);
                 let res = data.dfg_mut().new_value().binary(BinaryOp::Sub, zero, val);
@@ -1295,7 +1292,7 @@ impl UnaryExp {
                 data.layout_mut().bb_mut(*entry).insts_mut().extend([res]);
                 res
             }
-            UnaryExp::FuncCall(id, args) => {
+            UnaryExp::FuncCall(id, args, span) => {
                 if let Some(undefval) = var.get(id).cloned() {
                     match undefval {
                         IdentValue::Func(func) => {
@@ -1308,10 +1305,20 @@ impl UnaryExp {
                             data.layout_mut().bb_mut(*entry).insts_mut().extend([call]);
                             return call;
                         }
-                        _ => panic!("Variable {} used as function", id),
+                        _ => {
+                            report(Diagnostic::error(
+                                *span,
+                                format!("`{}` is not a function", id),
+                            ));
+                            return data.dfg_mut().new_value().integer(0);
+                        }
                     }
                 }
-                panic!("function {} not found", id);
+                report(Diagnostic::error(
+                    *span,
+                    format!("cannot find function `{}` in this scope", id),
+                ));
+                data.dfg_mut().new_value().integer(0)
             }
         }
     }
@@ -1331,7 +1338,7 @@ impl MulExp {
                 let right = unary_exp.gen_ir(data, entry, var);
                 if let Some(rv) = get_const_int(data, left) {
                     if let Some(rv1) = get_const_int(data, right) {
-                        return data.dfg_mut().new_value().integer(rv * rv1);
+                        return data.dfg_mut().new_value().integer(rv.wrapping_mul(rv1));
                     }
                 }
                 let res = data
@@ -1346,7 +1353,10 @@ impl MulExp {
                 let right = unary_exp.gen_ir(data, entry, var);
                 if let Some(rv) = get_const_int(data, left) {
                     if let Some(rv1) = get_const_int(data, right) {
-                        return data.dfg_mut().new_value().integer(rv / rv1);
+                        // 除以 0 留到运行时再说
+                        if let Some(res) = rv.checked_div(rv1) {
+                            return data.dfg_mut().new_value().integer(res);
+                        }
                     }
                 }
                 let res = data
@@ -1361,7 +1371,10 @@ impl MulExp {
                 let right = unary_exp.gen_ir(data, entry, var);
                 if let Some(rv) = get_const_int(data, left) {
                     if let Some(rv1) = get_const_int(data, right) {
-                        return data.dfg_mut().new_value().integer(rv % rv1);
+                        // 除以 0 留到运行时再说
+                        if let Some(res) = rv.checked_rem(rv1) {
+                            return data.dfg_mut().new_value().integer(res);
+                        }
                     }
                 }
                 let res = data
@@ -1389,7 +1402,7 @@ impl AddExp {
                 let right = mul_exp.gen_ir(data, entry, var);
                 if let Some(rv) = get_const_int(data, left) {
                     if let Some(rv1) = get_const_int(data, right) {
-                        return data.dfg_mut().new_value().integer(rv + rv1);
+                        return data.dfg_mut().new_value().integer(rv.wrapping_add(rv1));
                     }
                 }
                 let res = data
@@ -1404,7 +1417,7 @@ impl AddExp {
                 let right = mul_exp.gen_ir(data, entry, var);
                 if let Some(rv) = get_const_int(data, left) {
                     if let Some(rv1) = get_const_int(data, right) {
-                        return data.dfg_mut().new_value().integer(rv - rv1);
+                        return data.dfg_mut().new_value().integer(rv.wrapping_sub(rv1));
                     }
                 }
                 let res = data
@@ -1649,3 +1662,85 @@ impl LOrExp {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::optimize_loadstore::OptimizeLoadStore;
+    use crate::sysy::CompUnitParser;
+    use koopa::back::KoopaGenerator;
+    use std::cell::RefCell;
+
+    /// 函数 name 的 Koopa IR 文本
+    fn koopa_fun(src: &str, name: &str) -> String {
+        let strings = RefCell::new(Vec::new());
+        let mut ast = CompUnitParser::new().parse(&strings, src).unwrap();
+        ast.typecheck();
+        let mut program = ast.gen_ir();
+        program.optimize_loadstore();
+        let mut gen = KoopaGenerator::new(Vec::new());
+        gen.generate_on(&program).unwrap();
+        let text = String::from_utf8(gen.writer()).unwrap();
+        let start = text.find(&format!("fun @{}(", name)).unwrap();
+        let end = start + text[start..].find("\n}").unwrap();
+        text[start..end].to_string()
+    }
+
+    /// "%3 = call @newbar()" 里的 %3
+    fn result_of(fun: &str, call: &str) -> String {
+        let line = fun.lines().find(|l| l.ends_with(call)).unwrap();
+        line.trim().split(' ').next().unwrap().to_string()
+    }
+
+    #[test]
+    fn bar_and_score_parameters_are_copied() {
+        let src = "void add(Bar b, Note n, Score s, Track t) { b.push_note(n); s.push_bar(b); }
+            int main() { Bar b; Note n(syllablename = \"1\", duration = \"1/4\"); Score s; Track t;
+                add(b, n, s, t); return 0; }";
+        let fun = koopa_fun(src, "add");
+        let params: Vec<&str> = fun["fun @add(".len()..fun.find(')').unwrap()]
+            .split(", ")
+            .map(|p| p.split(':').next().unwrap())
+            .collect();
+        // 函数里改的是进入函数时复制出来的新对象，调用者的 Bar 和 Score 不变
+        let bar = result_of(&fun, "= call @newbar()");
+        let score = result_of(&fun, "= call @newscore()");
+        assert!(fun.contains(&format!("call @bar_copy({}, {})", bar, params[0])));
+        assert!(fun.contains(&format!("call @score_copy({}, {})", score, params[2])));
+        assert!(fun.contains(&format!("call @bar_push({}, ", bar)));
+        assert!(fun.contains(&format!("call @score_push({}, {})", score, bar)));
+        // Note 和 Track 直接传句柄
+        assert!(fun.contains(&format!("store {}, ", params[1])));
+        assert!(fun.contains(&format!("store {}, ", params[3])));
+        assert!(!fun.contains("newnote") && !fun.contains("newtrack"));
+    }
+
+    /// 解析失败时的报错
+    fn parse_error(src: &str) -> String {
+        let strings = RefCell::new(Vec::new());
+        let err = CompUnitParser::new().parse(&strings, src).err().unwrap();
+        crate::diagnostic::from_parse_error(err, 0).message
+    }
+
+    #[test]
+    fn integer_literals() {
+        let main = |exp: &str| koopa_fun(&format!("int main() {{ return {}; }}", exp), "main");
+        assert!(main("-2147483648").contains("ret -2147483648"));
+        assert!(main("- 2147483648 + 1").contains("ret -2147483647"));
+        assert!(main("0xffffffff").contains("ret -1"));
+        assert!(main("020000000000").contains("ret -2147483648"));
+        assert!(main("0x7fffffff").contains("ret 2147483647"));
+        for (lit, exp) in [
+            ("3000000000", "3000000000"),
+            ("2147483648", "2147483648"),
+            ("-(2147483648)", "2147483648"),
+            ("1 - 2147483648", "2147483648"),
+            ("0x100000000", "0x100000000"),
+            ("040000000000", "040000000000"),
+        ] {
+            assert_eq!(
+                parse_error(&format!("int main() {{ return {}; }}", lit)),
+                format!("integer literal `{}` is too large", exp)
+            );
+        }
+    }
+}
@@ -0,0 +1,721 @@
+// 语义检查：在生成 IR 之前给每个变量和表达式标上类型。
+// 到了 IR 里 Note/Bar/Score/Track/Instrument 都只是 i32 句柄，混用了也能编译通过，
+// 运行时才会去错误的表里取东西，所以要在这里先把类型对不上的地方报出来。
+
+use crate::ast::*;
+use crate::diagnostic::*;
+use crate::libfuncs::*;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpType {
+    Int,
+    Array(usize), // 还剩几维没有取下标；字符串字面量是 Array(1)
+    Note,
+    Bar,
+    Score,
+    Track,
+    Instrument,
+    Void,
+    Error, // 已经报过错了，和什么都兼容，避免一个错误报好几遍
+}
+
+impl fmt::Display for ExpType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpType::Int => write!(f, "int"),
+            ExpType::Array(dim) => write!(f, "int{}", "[]".repeat(*dim)),
+            ExpType::Note => write!(f, "Note"),
+            ExpType::Bar => write!(f, "Bar"),
+            ExpType::Score => write!(f, "Score"),
+            ExpType::Track => write!(f, "Track"),
+            ExpType::Instrument => write!(f, "Instrument"),
+            ExpType::Void => write!(f, "void"),
+            ExpType::Error => write!(f, "{{error}}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Symbol {
+    Var(ExpType),
+    Func(Vec<ExpType>, ExpType),
+}
+
+type Env = HashMap<String, Symbol>;
+
+fn lib_type(ty: LibType) -> ExpType {
+    match ty {
+        LibType::Unit => ExpType::Void,
+        LibType::Int => ExpType::Int,
+        LibType::IntPtr => ExpType::Array(1),
+    }
+}
+
+/// 检查 found 是不是 expected 类型，不是就报错
+fn expect(found: (ExpType, Span), expected: ExpType) {
+    let (ty, span) = found;
+    if ty != expected && ty != ExpType::Error && expected != ExpType::Error {
+        report(Diagnostic::error(
+            span,
+            format!("mismatched types: expected `{}`, found `{}`", expected, ty),
+        ));
+    }
+}
+
+impl CompUnit {
+    /// 全局变量先于所有函数，函数之间可以互相调用，这和 gen_ir 的处理顺序一致。
+    /// 要看类型才能决定怎么生成的语句（音源是 Instrument 的 sing）在这里改写
+    pub fn typecheck(&mut self) {
+        let mut env: Env = HashMap::new();
+        for func in LIBFUNCS.iter() {
+            let params = func.params.iter().map(|p| lib_type(*p)).collect();
+            env.insert(func.name.into(), Symbol::Func(params, lib_type(func.ret)));
+        }
+        // 全局的音乐对象在运行时构造，初始化时可以调用任何函数
+        for func in self.func_defs.iter() {
+            let params = func.params.iter().map(|p| p.exp_type()).collect();
+            env.insert(
+                func.id.clone(),
+                Symbol::Func(params, func.func_type.exp_type()),
+            );
+        }
+        for decl in self.global_var_defs.iter() {
+            decl.typecheck(&mut env);
+        }
+        for func in self.func_defs.iter_mut() {
+            func.typecheck(&env);
+        }
+    }
+}
+
+impl FuncType {
+    fn exp_type(&self) -> ExpType {
+        match self {
+            FuncType::Int => ExpType::Int,
+            FuncType::Void => ExpType::Void,
+            FuncType::Music(ty) => ty.exp_type(),
+        }
+    }
+}
+
+impl MusicType {
+    fn exp_type(&self) -> ExpType {
+        match self {
+            MusicType::Note => ExpType::Note,
+            MusicType::Bar => ExpType::Bar,
+            MusicType::Score => ExpType::Score,
+            MusicType::Track => ExpType::Track,
+            MusicType::Instrument => ExpType::Instrument,
+        }
+    }
+}
+
+impl FuncParam {
+    fn exp_type(&self) -> ExpType {
+        match self {
+            FuncParam::Var(_) => ExpType::Int,
+            FuncParam::Music(ty, _) => ty.exp_type(),
+            FuncParam::Array(_, exps) => ExpType::Array(exps.len() + 1),
+        }
+    }
+
+    fn typecheck(&self, env: &Env) -> (String, ExpType) {
+        match self {
+            FuncParam::Var(id) | FuncParam::Music(_, id) => (id.clone(), self.exp_type()),
+            FuncParam::Array(id, exps) => {
+                for exp in exps.iter() {
+                    expect(exp.typecheck(env), ExpType::Int);
+                }
+                (id.clone(), self.exp_type())
+            }
+        }
+    }
+}
+
+impl FuncDef {
+    fn typecheck(&mut self, env: &Env) {
+        let mut myenv = env.clone();
+        for param in self.params.iter() {
+            let (id, ty) = param.typecheck(env);
+            myenv.insert(id, Symbol::Var(ty));
+        }
+        let ret = self.func_type.exp_type();
+        self.block.typecheck(&mut myenv, ret);
+        // int 函数和 C 一样允许走到结尾，生成代码时补上 ret 0；音乐对象没有可以补的默认值
+        if ret != ExpType::Void && ret != ExpType::Int && !self.block.returns() {
+            report(Diagnostic::error(
+                self.span,
+                format!("function `{}` may not return a value", self.id),
+            ));
+        }
+    }
+}
+
+impl Block {
+    fn typecheck(&mut self, env: &mut Env, ret: ExpType) {
+        let mut myenv = env.clone();
+        for item in self.vecitem.iter_mut() {
+            match item.as_mut() {
+                BlockItem::Stmt(stmt) => stmt.typecheck(&mut myenv, ret),
+                BlockItem::Decl(decl) => decl.typecheck(&mut myenv),
+            }
+        }
+    }
+
+    fn stmts(&self) -> impl Iterator<Item = &Stmt> {
+        self.vecitem.iter().filter_map(|item| match item.as_ref() {
+            BlockItem::Stmt(stmt) => Some(stmt.as_ref()),
+            BlockItem::Decl(_) => None,
+        })
+    }
+
+    fn returns(&self) -> bool {
+        self.stmts().any(|stmt| stmt.returns())
+    }
+}
+
+impl Decl {
+    fn typecheck(&self, env: &mut Env) {
+        for def in self.defs.iter() {
+            def.typecheck(env);
+        }
+    }
+}
+
+impl ArrayInit {
+    fn typecheck(&self, env: &mut Env) {
+        match self {
+            ArrayInit::Single(exp) => expect(exp.typecheck(env), ExpType::Int),
+            ArrayInit::Multiple(inits, _) => {
+                for init in inits.iter() {
+                    init.typecheck(env);
+                }
+            }
+        }
+    }
+}
+
+impl VarDef {
+    fn typecheck(&self, env: &mut Env) {
+        let (id, ty) = match self {
+            VarDef::Ident(id) => (id, ExpType::Int),
+            VarDef::IdentInit(id, exp) | VarDef::ConstIdentInit(id, exp) => {
+                expect(exp.typecheck(env), ExpType::Int);
+                (id, ExpType::Int)
+            }
+            VarDef::Array(id, exps) => {
+                for exp in exps.iter() {
+                    expect(exp.typecheck(env), ExpType::Int);
+                }
+                (id, ExpType::Array(exps.len()))
+            }
+            VarDef::ArrayInit(id, exps, init) => {
+                for exp in exps.iter() {
+                    expect(exp.typecheck(env), ExpType::Int);
+                }
+                init.typecheck(env);
+                (id, ExpType::Array(exps.len()))
+            }
+            VarDef::NoteInit(id, _, _, _) => (id, ExpType::Note),
+            VarDef::BarInit(id, _) | VarDef::Bar(id) => (id, ExpType::Bar),
+            VarDef::BarCopy(id, y) => {
+                expect(y.typecheck(env), ExpType::Bar);
+                (id, ExpType::Bar)
+            }
+            VarDef::ScoreInit(id, _) | VarDef::Score(id) => (id, ExpType::Score),
+            VarDef::ScoreCopy(id, y) => {
+                expect(y.typecheck(env), ExpType::Score);
+                (id, ExpType::Score)
+            }
+            VarDef::Track(id) => (id, ExpType::Track),
+            VarDef::TrackLoad(id, y) => {
+                expect(y.typecheck(env), ExpType::Array(1));
+                (id, ExpType::Track)
+            }
+            VarDef::TrackCopy(id, y, z) => {
+                expect(y.typecheck(env), ExpType::Array(1));
+                expect(z.typecheck(env), ExpType::Array(1));
+                (id, ExpType::Track)
+            }
+            VarDef::Instrument(id) => (id, ExpType::Instrument),
+            VarDef::MusicBind(ty, id, exp) => {
+                expect(exp.typecheck(env), ty.exp_type());
+                (id, ty.exp_type())
+            }
+        };
+        env.insert(id.clone(), Symbol::Var(ty));
+    }
+}
+
+impl Stmt {
+    /// 执行完这条语句时一定已经 return 了；没有 break 的 while (1) 不会正常结束，也算
+    fn returns(&self) -> bool {
+        match self {
+            Stmt::Return(..) => true,
+            Stmt::Block(block) => block.returns(),
+            Stmt::IfElse(_, stmt1, stmt2) => stmt1.returns() && stmt2.returns(),
+            Stmt::While(cond, body) => cond.is_nonzero_literal() && !body.breaks(),
+            _ => false,
+        }
+    }
+
+    /// 有跳出当前这层循环的 break；内层 while 里的 break 不算
+    fn breaks(&self) -> bool {
+        match self {
+            Stmt::Break(_) => true,
+            Stmt::Block(block) => block.stmts().any(|stmt| stmt.breaks()),
+            Stmt::If(_, stmt) => stmt.breaks(),
+            Stmt::IfElse(_, stmt1, stmt2) => stmt1.breaks() || stmt2.breaks(),
+            _ => false,
+        }
+    }
+
+    /// 音乐对象方法的名字，以及接收者和各个参数应有的类型
+    fn method_signature(&self) -> Option<(&'static str, Vec<(&Exp, ExpType)>)> {
+        use ExpType::*;
+        let sig = match self {
+            Stmt::Sing(x, a, b, c, d, e) => (
+                "sing",
+                vec![
+                    (x, Score),
+                    (a, Array(1)),
+                    (b, Array(1)),
+                    (c, Int),
+                    (d, Int),
+                    (e, Int),
+                ],
+            ),
+            Stmt::SingInstrument(x, a, b, c, d, e) => (
+                "sing",
+                vec![
+                    (x, Score),
+                    (a, Instrument),
+                    (b, Array(1)),
+                    (c, Int),
+                    (d, Int),
+                    (e, Int),
+                ],
+            ),
+            Stmt::SingPreset(x, a, p, b, c, d, e) => (
+                "sing",
+                vec![
+                    (x, Score),
+                    (a, Array(1)),
+                    (p, Int),
+                    (b, Array(1)),
+                    (c, Int),
+                    (d, Int),
+                    (e, Int),
+                ],
+            ),
+            Stmt::PushBar(x, a) => ("push_bar", vec![(x, Score), (a, Bar)]),
+            Stmt::PushNote(x, a) => ("push_note", vec![(x, Bar), (a, Note)]),
+            Stmt::SetBarBpm(x, a) => ("set_bar_bpm", vec![(x, Bar), (a, Int)]),
+            Stmt::SetScoreBpm(x, a) => ("set_score_bpm", vec![(x, Score), (a, Int)]),
+            Stmt::Append(x, a) => ("append", vec![(x, Score), (a, Score)]),
+            Stmt::ReplaceBar(x, a, b) => ("replace_bar", vec![(x, Score), (a, Int), (b, Bar)]),
+            Stmt::IncScorePitch(x, a) => ("inc_score_pitch", vec![(x, Score), (a, Int)]),
+            Stmt::IncBarPitch(x, a) => ("inc_bar_pitch", vec![(x, Bar), (a, Int)]),
+            Stmt::SetScoreDuration(x, a) => ("set_score_duration", vec![(x, Score), (a, Int)]),
+            Stmt::SetEnvelope(x, a, d, s, r) => (
+                "set_envelope",
+                vec![(x, Score), (a, Int), (d, Int), (s, Int), (r, Int)],
+            ),
+            Stmt::ExportMidi(x, a) => ("export_midi", vec![(x, Score), (a, Array(1))]),
+            Stmt::AddSample(x, a, b) => ("add_sample", vec![(x, Instrument), (a, Array(1)), (b, Int)]),
+            Stmt::SetBarDuration(x, a) => ("set_bar_duration", vec![(x, Bar), (a, Int)]),
+            Stmt::AppendTrack(x, a) => ("append_track", vec![(x, Track), (a, Track)]),
+            Stmt::StackTrack(x, a) => ("stack", vec![(x, Track), (a, Track)]),
+            Stmt::StackTrackAt(x, a, b) => ("stack_at", vec![(x, Track), (a, Track), (b, Int)]),
+            Stmt::Pan(x, a) => ("pan", vec![(x, Track), (a, Int)]),
+            Stmt::GainDb(x, a) => ("gain_db", vec![(x, Track), (a, Int)]),
+            Stmt::SetVol(x, a, b) => ("setvol", vec![(x, Track), (a, Int), (b, Int)]),
+            Stmt::AppendSilence(x, a, b, c, d) => (
+                "append_silence",
+                vec![(x, Track), (a, Int), (b, Int), (c, Int), (d, Int)],
+            ),
+            _ => return None,
+        };
+        let (name, args) = sig;
+        Some((
+            name,
+            args.into_iter().map(|(e, t)| (e.as_ref(), t)).collect(),
+        ))
+    }
+
+    fn typecheck(&mut self, env: &mut Env, ret: ExpType) {
+        if let Some((name, args)) = self.method_signature() {
+            let (receiver, expected) = args[0];
+            let (ty, span) = receiver.typecheck(env);
+            if ty != expected && ty != ExpType::Error {
+                report(Diagnostic::error(
+                    span,
+                    format!("no method named `{}` found for `{}`", name, ty),
+                ));
+            }
+            let mut instrument = false;
+            for (i, (arg, expected)) in args.iter().enumerate().skip(1) {
+                let found = arg.typecheck(env);
+                // sing 的音源除了文件名，也可以是 Instrument
+                if i == 1 && found.0 == ExpType::Instrument && matches!(self, Stmt::Sing(..)) {
+                    instrument = true;
+                    continue;
+                }
+                expect(found, *expected);
+            }
+            if let (true, Stmt::Sing(x, a, b, c, d, e)) = (instrument, &*self) {
+                *self = Stmt::SingInstrument(
+                    x.clone(),
+                    a.clone(),
+                    b.clone(),
+                    c.clone(),
+                    d.clone(),
+                    e.clone(),
+                );
+            }
+            return;
+        }
+        match self {
+            Stmt::Assign(lval, exp) => {
+                let (ty, span) = lval.typecheck(env);
+                if let ExpType::Array(_) = ty {
+                    report(Diagnostic::error(span, "cannot assign to an array"));
+                    exp.typecheck(env);
+                } else {
+                    expect(exp.typecheck(env), ty);
+                }
+            }
+            Stmt::Return(optexp, span) => match (optexp.as_ref(), ret) {
+                (Some(exp), ExpType::Void) => {
+                    report(Diagnostic::error(
+                        exp.span,
+                        "cannot return a value from a `void` function",
+                    ));
+                }
+                (Some(exp), _) => expect(exp.typecheck(env), ret),
+                (None, ExpType::Void) => {}
+                (None, _) => {
+                    report(Diagnostic::error(
+                        *span,
+                        format!("missing return value in a function returning `{}`", ret),
+                    ));
+                }
+            },
+            Stmt::Do(optexp) => {
+                // 单独的表达式语句可以是 void 函数调用
+                if let Some(exp) = optexp.as_ref() {
+                    exp.typecheck(env);
+                }
+            }
+            Stmt::Block(block) => block.typecheck(env, ret),
+            Stmt::If(cond, stmt) | Stmt::While(cond, stmt) => {
+                expect(cond.typecheck(env), ExpType::Int);
+                let mut myenv = env.clone();
+                stmt.typecheck(&mut myenv, ret);
+            }
+            Stmt::IfElse(cond, stmt1, stmt2) => {
+                expect(cond.typecheck(env), ExpType::Int);
+                let mut myenv1 = env.clone();
+                let mut myenv2 = env.clone();
+                stmt1.typecheck(&mut myenv1, ret);
+                stmt2.typecheck(&mut myenv2, ret);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Exp {
+    fn typecheck(&self, env: &Env) -> (ExpType, Span) {
+        (self.lorexp.typecheck(env, self.span).0, self.span)
+    }
+
+    /// 是不是单独一个非零的整数字面量，例如 while (1) 的条件
+    fn is_nonzero_literal(&self) -> bool {
+        let LOrExp::LAndExp(exp) = self.lorexp.as_ref() else {
+            return false;
+        };
+        let LAndExp::EqExp(exp) = exp.as_ref() else {
+            return false;
+        };
+        let EqExp::RelExp(exp) = exp.as_ref() else {
+            return false;
+        };
+        let RelExp::AddExp(exp) = exp.as_ref() else {
+            return false;
+        };
+        let AddExp::MulExp(exp) = exp.as_ref() else {
+            return false;
+        };
+        let MulExp::UnaryExp(exp) = exp.as_ref() else {
+            return false;
+        };
+        let UnaryExp::PrimaryExp(exp) = exp.as_ref() else {
+            return false;
+        };
+        match exp.as_ref() {
+            PrimaryExp::Number(n) => *n != 0,
+            PrimaryExp::Exp(exp) => exp.is_nonzero_literal(),
+            _ => false,
+        }
+    }
+}
+
+/// 二元运算的两边都必须是 int
+fn binary(l: (ExpType, Span), r: (ExpType, Span), span: Span) -> (ExpType, Span) {
+    expect(l, ExpType::Int);
+    expect(r, ExpType::Int);
+    (ExpType::Int, span)
+}
+
+// 下面每一层都返回类型和尽量精确的位置，没有位置的节点用外层表达式的位置
+
+impl LOrExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            LOrExp::LAndExp(e) => e.typecheck(env, span),
+            LOrExp::Or(l, r) => binary(l.typecheck(env, span), r.typecheck(env, span), span),
+        }
+    }
+}
+
+impl LAndExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            LAndExp::EqExp(e) => e.typecheck(env, span),
+            LAndExp::And(l, r) => binary(l.typecheck(env, span), r.typecheck(env, span), span),
+        }
+    }
+}
+
+impl EqExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            EqExp::RelExp(e) => e.typecheck(env, span),
+            EqExp::Eq(l, r) | EqExp::Ne(l, r) => {
+                binary(l.typecheck(env, span), r.typecheck(env, span), span)
+            }
+        }
+    }
+}
+
+impl RelExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            RelExp::AddExp(e) => e.typecheck(env, span),
+            RelExp::Lt(l, r) | RelExp::Le(l, r) | RelExp::Gt(l, r) | RelExp::Ge(l, r) => {
+                binary(l.typecheck(env, span), r.typecheck(env, span), span)
+            }
+        }
+    }
+}
+
+impl AddExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            AddExp::MulExp(e) => e.typecheck(env, span),
+            AddExp::Add(l, r) | AddExp::Sub(l, r) => {
+                binary(l.typecheck(env, span), r.typecheck(env, span), span)
+            }
+        }
+    }
+}
+
+impl MulExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            MulExp::UnaryExp(e) => e.typecheck(env, span),
+            MulExp::Mul(l, r) | MulExp::Div(l, r) | MulExp::Mod(l, r) => {
+                binary(l.typecheck(env, span), r.typecheck(env, span), span)
+            }
+        }
+    }
+}
+
+impl UnaryExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            UnaryExp::PrimaryExp(e) => e.typecheck(env, span),
+            UnaryExp::Pos(e) | UnaryExp::Neg(e) | UnaryExp::Not(e) => {
+                expect(e.typecheck(env, span), ExpType::Int);
+                (ExpType::Int, span)
+            }
+            UnaryExp::FuncCall(id, args, callspan) => {
+                let (params, ret) = match env.get(id) {
+                    Some(Symbol::Func(params, ret)) => (params.clone(), *ret),
+                    Some(Symbol::Var(_)) => {
+                        report(Diagnostic::error(
+                            *callspan,
+                            format!("`{}` is not a function", id),
+                        ));
+                        return (ExpType::Error, *callspan);
+                    }
+                    None => {
+                        report(Diagnostic::error(
+                            *callspan,
+                            format!("cannot find function `{}` in this scope", id),
+                        ));
+                        return (ExpType::Error, *callspan);
+                    }
+                };
+                if params.len() != args.len() {
+                    report(Diagnostic::error(
+                        *callspan,
+                        format!(
+                            "function `{}` takes {} argument(s) but {} were supplied",
+                            id,
+                            params.len(),
+                            args.len()
+                        ),
+                    ));
+                }
+                for (arg, param) in args.iter().zip(params.iter()) {
+                    expect(arg.typecheck(env), *param);
+                }
+                (ret, *callspan)
+            }
+        }
+    }
+}
+
+impl PrimaryExp {
+    fn typecheck(&self, env: &Env, span: Span) -> (ExpType, Span) {
+        match self {
+            PrimaryExp::Exp(e) => e.typecheck(env),
+            PrimaryExp::Number(_) => (ExpType::Int, span),
+            PrimaryExp::Str(_, strspan) => (ExpType::Array(1), *strspan),
+            PrimaryExp::LVal(lval) => lval.typecheck(env),
+        }
+    }
+}
+
+impl LVal {
+    fn typecheck(&self, env: &Env) -> (ExpType, Span) {
+        let (id, exps, span) = match self {
+            LVal::Ident(id, span) => (id, &Vec::new(), *span),
+            LVal::Array(id, exps, span) => (id, exps, *span),
+        };
+        for exp in exps.iter() {
+            expect(exp.typecheck(env), ExpType::Int);
+        }
+        let ty = match env.get(id) {
+            Some(Symbol::Var(ty)) => *ty,
+            Some(Symbol::Func(_, _)) => {
+                report(Diagnostic::error(
+                    span,
+                    format!("function `{}` used as a variable", id),
+                ));
+                return (ExpType::Error, span);
+            }
+            None => {
+                report(Diagnostic::error(
+                    span,
+                    format!("cannot find variable `{}` in this scope", id),
+                ));
+                return (ExpType::Error, span);
+            }
+        };
+        if exps.is_empty() {
+            return (ty, span);
+        }
+        match ty {
+            ExpType::Array(dim) if dim > exps.len() => (ExpType::Array(dim - exps.len()), span),
+            ExpType::Array(dim) if dim == exps.len() => (ExpType::Int, span),
+            ExpType::Error => (ExpType::Error, span),
+            _ => {
+                report(Diagnostic::error(
+                    span,
+                    format!("cannot index into a value of type `{}`", ty),
+                ));
+                (ExpType::Error, span)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Stmt;
+    use crate::diagnostic::take_diagnostics;
+    use crate::sysy::CompUnitParser;
+    use std::cell::RefCell;
+
+    fn check(src: &str) -> Vec<String> {
+        let strings = RefCell::new(Vec::new());
+        let mut ast = CompUnitParser::new().parse(&strings, src).unwrap();
+        ast.typecheck();
+        take_diagnostics().into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn sing_with_instrument() {
+        let strings = RefCell::new(Vec::new());
+        let src = "int main() { Score s; Instrument i; s.sing(i, \"a.wav\", 44100, 2, 1);
+            s.sing(\"a.wav\", \"b.wav\", 44100, 2, 1); return 0; }";
+        let mut ast = CompUnitParser::new().parse(&strings, src).unwrap();
+        ast.typecheck();
+        assert!(take_diagnostics().is_empty());
+        let stmts: Vec<&Stmt> = ast.func_defs[0].block.stmts().collect();
+        assert!(matches!(stmts[0], Stmt::SingInstrument(..)));
+        assert!(matches!(stmts[1], Stmt::Sing(..)));
+    }
+
+    #[test]
+    fn music_functions() {
+        let src = "Bar chorus(int k) { Bar b; return b; }
+            void harmonize(Score s, Bar b) { s.push_bar(b); }
+            int main() { Score s; Bar b = chorus(1); harmonize(s, b); return 0; }";
+        assert!(check(src).is_empty());
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let src = "int main() { Bar b; Score s; int x = b; s.push_bar(s); b.set_score_bpm(90); return 0; }";
+        assert_eq!(
+            check(src),
+            [
+                "mismatched types: expected `int`, found `Bar`",
+                "mismatched types: expected `Bar`, found `Score`",
+                "no method named `set_score_bpm` found for `Bar`",
+            ]
+        );
+    }
+
+    #[test]
+    fn return_types() {
+        let src = "Bar f() { return 1; } void g() { return 1; } int h() { return; }";
+        assert_eq!(
+            check(src),
+            [
+                "mismatched types: expected `Bar`, found `int`",
+                "cannot return a value from a `void` function",
+                "missing return value in a function returning `int`",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_return() {
+        let src = "Bar f(int x) { Bar b; if (x) return b; }
+            Bar g(int x) { Bar b; if (x) return b; else { return b; } }
+            Bar h() { Bar b; while (1) { if (getint()) return b; } }
+            Bar k() { Bar b; while (1) { if (getint()) break; } }
+            void v() {}";
+        assert_eq!(
+            check(src),
+            [
+                "function `f` may not return a value",
+                "function `k` may not return a value",
+            ]
+        );
+    }
+
+    #[test]
+    fn int_functions_may_fall_through() {
+        let src = "int g(int x) { if (x) return 1; }
+            int main() { putint(1); }";
+        assert_eq!(check(src), Vec::<String>::new());
+    }
+}
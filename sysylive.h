@@ -0,0 +1,57 @@
+/* generated by `compiler --emit-runtime-header`, do not edit */
+#ifndef SYSYLIVE_H
+#define SYSYLIVE_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+int getint(void);
+int getch(void);
+int getarray(int *);
+void putint(int);
+void putch(int);
+void putarray(int, int *);
+void starttime(void);
+void stoptime(void);
+void track_copy(int, int *, int *);
+void track_load(int, int *);
+void score_sing(int, int *, int *, int, int, int);
+void score_sing_preset(int, int *, int, int *, int, int, int);
+void score_sing_instrument(int, int, int *, int, int, int);
+void score_export_midi(int, int *);
+int newinstrument(void);
+void instrument_add_sample(int, int *, int);
+void score_push(int, int);
+void bar_push(int, int);
+int newnote(int, int, int, int);
+int newnote_rest(int, int, int);
+int note_add_pitch(int, int);
+int note_articulate(int, int);
+int newbar(void);
+int newscore(void);
+int newtrack(void);
+void bar_setbpm(int, int);
+void score_setbpm(int, int);
+void score_append(int, int);
+void bar_copy(int, int);
+void score_copy(int, int);
+void score_replace(int, int, int);
+void score_inc_pitch(int, int);
+void bar_inc_pitch(int, int);
+void score_set_duration(int, int);
+void score_set_envelope(int, int, int, int, int);
+void bar_set_duration(int, int);
+void track_append(int, int);
+void track_stack(int, int);
+void track_stack_at(int, int, int);
+void track_pan(int, int);
+void track_gain_db(int, int);
+void track_set_volume(int, int, int);
+void track_append_silence(int, int, int, int, int);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif